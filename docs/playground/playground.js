@@ -1,9 +1,12 @@
 import { parseTerm } from "./term.js";
 import { newState, State } from "./am.js";
+import { parseTrace, Replay } from "./trace.js";
 
 window.parseTerm = parseTerm;
 window.newState = newState;
 window.State = State;
+window.parseTrace = parseTrace;
+window.Replay = Replay;
 
 function randint(max) {
   return Math.floor(Math.random() * max);
@@ -0,0 +1,76 @@
+// Replaying traces written by `unabs --trace out.jsonl`
+//
+// A trace has one JSON object per line, one line per transition, each
+// describing the state the transition is taken from: its kind (`state`),
+// the rule that applies (`rule`), the term and where it is in the source
+// (`term`, `span`, `at`), the value and argument (`v`, `w`), the depth of
+// the continuation, the bytes printed once the transition is taken
+// (`output`) and the character it prints (`printed`). Terms and values are
+// cut short at 60 characters. See src/machines/trace.rs for the details.
+
+function parseTrace(text) {
+  return text
+    .split("\n")
+    .filter((line) => line.trim() !== "")
+    .map((line) => JSON.parse(line));
+}
+
+// The name a trace gives the rule a state applies
+function ruleOf(state) {
+  switch (state.type) {
+    case "Eval":
+      return state.data.type === "App" ? "App" : "Atom";
+    case "ApplyT":
+      return state.data[0].type === "D0" ? "Delay" : "Arg";
+    case "ApplyV":
+      return state.data[0].type;
+    case "ApplyK":
+      return state.kont ? state.kont.type : "Return";
+  }
+}
+
+// Steps through a trace, and, given the state the traced program starts
+// in, runs the machine of am.js alongside to check that both agree.
+class Replay {
+  constructor(entries, state = null) {
+    this.entries = entries;
+    this.index = 0;
+    this.state = state;
+    this.mismatch = null;
+  }
+
+  // The transition about to be replayed, or null at the end
+  current() {
+    return this.entries[this.index] || null;
+  }
+
+  done() {
+    return this.index >= this.entries.length;
+  }
+
+  // Replays one transition; false at the end of the trace
+  step() {
+    const entry = this.current();
+    if (entry === null) return false;
+    if (this.state && this.mismatch === null) {
+      const rule = ruleOf(this.state);
+      if (this.state.type !== entry.state || rule !== entry.rule) {
+        this.mismatch = `step ${entry.step}: traced ${entry.state} ${entry.rule}, ran ${this.state.type} ${rule}`;
+      } else {
+        this.state = this.state.step();
+      }
+    }
+    this.index++;
+    return true;
+  }
+
+  // What the trace printed up to where it is
+  output() {
+    return this.entries
+      .slice(0, this.index)
+      .map((entry) => entry.printed || "")
+      .join("");
+  }
+}
+
+export { parseTrace, ruleOf, Replay };
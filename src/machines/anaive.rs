@@ -1,7 +1,15 @@
+use super::interrupt::{self, Choice, Interrupts};
+use super::output::{self, Flush};
+use super::readback::{readback, Readback, Shape};
+use super::render::{Bounds, Frame, Render, Renderer};
+use super::snapshot::{self, save, Builder, Node, Snapshot};
+use super::stats::{self, Stats, ValueKind};
+use super::{print_result, Options, Outcome};
+use crate::term::{Program, Term};
 use std::char;
 use std::fmt::Display;
 use std::io::Write;
-use crate::term::Term;
+use std::sync::Arc;
 
 // A copying abstract machine for unlambda
 // Not the most efficient implementation!
@@ -52,6 +60,92 @@ impl Display for Value {
     }
 }
 
+// Nothing is shared in this machine, so nothing is labelled
+impl Render for Value {
+    fn render(&self, r: &mut Renderer) {
+        match self {
+            Value::S1(w) => {
+                r.text("`s");
+                r.part(w.as_ref());
+            }
+            Value::S2(w0, w1) => {
+                r.text("``s");
+                r.part(w0.as_ref());
+                r.part(w1.as_ref());
+            }
+            Value::K1(w) => {
+                r.text("`k");
+                r.part(w.as_ref());
+            }
+            Value::D1T(t) => {
+                r.text("`d[");
+                r.part(t.as_ref());
+                r.char(']');
+            }
+            Value::D1V(w) => {
+                r.text("`d");
+                r.part(w.as_ref());
+            }
+            Value::C1(k) => {
+                r.text("`c(");
+                if let Some(k) = k.as_ref() {
+                    r.part(k);
+                }
+                r.char(')');
+            }
+            // atoms print the same either way
+            v => r.text(&v.to_string()),
+        }
+    }
+}
+
+impl Value {
+    // What kind of value this is, as counted in stats
+    fn kind(&self) -> ValueKind {
+        match self {
+            Value::I0 => ValueKind::I0,
+            Value::S0 => ValueKind::S0,
+            Value::K0 => ValueKind::K0,
+            Value::V0 => ValueKind::V0,
+            Value::D0 => ValueKind::D0,
+            Value::C0 => ValueKind::C0,
+            Value::Put0(_) => ValueKind::Put0,
+            Value::S1(_) => ValueKind::S1,
+            Value::S2(..) => ValueKind::S2,
+            Value::K1(_) => ValueKind::K1,
+            Value::D1T(_) => ValueKind::D1T,
+            Value::D1V(_) => ValueKind::D1V,
+            Value::C1(_) => ValueKind::C1,
+        }
+    }
+
+    /// A term that evaluates to this value, or `None` if it holds a
+    /// continuation.
+    pub fn readback(&self) -> Option<Term> {
+        readback(self).map(|t| t.as_ref().clone())
+    }
+}
+
+impl Readback for Value {
+    fn shape(&self) -> Shape<'_, Self> {
+        match self {
+            Value::I0 => Shape::Atom(Term::I),
+            Value::S0 => Shape::Atom(Term::S),
+            Value::K0 => Shape::Atom(Term::K),
+            Value::V0 => Shape::Atom(Term::V),
+            Value::D0 => Shape::Atom(Term::D),
+            Value::C0 => Shape::Atom(Term::C),
+            Value::Put0(c) => Shape::Atom(Term::Put(*c)),
+            Value::S1(w) => Shape::S1(w),
+            Value::S2(w0, w1) => Shape::S2(w0, w1),
+            Value::K1(w) => Shape::K1(w),
+            Value::D1T(t) => Shape::D1T(Arc::new(t.as_ref().clone())),
+            Value::D1V(w) => Shape::D1V(w),
+            Value::C1(_) => Shape::C1,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Kont {
     BindT(Box<Term>, Box<Option<Kont>>),
@@ -61,60 +155,127 @@ pub enum Kont {
 }
 impl Display for Kont {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut wrapped = "()".to_string();
-        let mut current = self;
-        loop {
-            let (next, text) = match current {
-                Kont::BindT(t, k) => (k, format!("`{}[{}]", wrapped, t)),
-                Kont::BindV(v, k) => (k, format!("`{}{}", v, wrapped)),
-                Kont::BindW(w, k) => (k, format!("`{}{}", wrapped, w)),
-                Kont::SWait(v1, v, k) => (k, format!("`{}`{}{}", wrapped, v1, v)),
-            };
-            match next.as_ref() {
-                Some(k) => {
-                    wrapped = text;
-                    current = k;
-                }
-                None => {
-                    return write!(f, "{}", text);
-                }
+        // each frame wraps the ones above it, so the bottom one goes first
+        let frames: Vec<&Kont> = self.frames().collect();
+        for frame in frames.iter().rev() {
+            match frame {
+                Kont::BindV(v, _) => write!(f, "`{}", v)?,
+                _ => write!(f, "`")?,
+            }
+        }
+        write!(f, "()")?;
+        for frame in frames {
+            match frame {
+                Kont::BindT(t, _) => write!(f, "[{}]", t)?,
+                Kont::BindV(..) => {}
+                Kont::BindW(w, _) => write!(f, "{}", w)?,
+                Kont::SWait(v1, v, _) => write!(f, "`{}{}", v1, v)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Render for Kont {
+    fn render(&self, r: &mut Renderer) {
+        r.kont(self.frames());
+    }
+}
+
+impl Frame for Kont {
+    fn before(&self, r: &mut Renderer) {
+        r.char('`');
+        if let Kont::BindV(v, _) = self {
+            r.part(v.as_ref());
+        }
+    }
+
+    fn after(&self, r: &mut Renderer) {
+        match self {
+            Kont::BindT(t, _) => {
+                r.char('[');
+                r.part(t.as_ref());
+                r.char(']');
+            }
+            Kont::BindV(..) => {}
+            Kont::BindW(w, _) => r.part(w.as_ref()),
+            Kont::SWait(v1, v, _) => {
+                r.char('`');
+                r.part(v1.as_ref());
+                r.part(v.as_ref());
             }
         }
     }
 }
 
-pub enum State {
+impl Kont {
+    // This frame and the ones below it, from the top
+    fn frames(&self) -> impl Iterator<Item = &Kont> {
+        std::iter::successors(Some(self), |k| k.next().as_ref())
+    }
+
+    fn next(&self) -> &Option<Kont> {
+        match self {
+            Kont::BindT(_, k) | Kont::BindV(_, k) | Kont::BindW(_, k) | Kont::SWait(_, _, k) => k,
+        }
+    }
+
+    // The same frame, on top of another continuation
+    fn on(mut self, k: Option<Kont>) -> Self {
+        match &mut self {
+            Kont::BindT(_, next)
+            | Kont::BindV(_, next)
+            | Kont::BindW(_, next)
+            | Kont::SWait(_, _, next) => **next = k,
+        }
+        self
+    }
+}
+
+// Number of frames in a continuation
+fn depth(k: &Option<Kont>) -> usize {
+    let mut depth = 0;
+    let mut current = k;
+    while let Some(k) = current {
+        depth += 1;
+        current = k.next();
+    }
+    depth
+}
+
+/// A configuration of the machine
+pub enum Config {
     Eval(Term, Option<Kont>),
     ApplyT(Value, Term, Option<Kont>),
     ApplyV(Value, Value, Option<Kont>),
     ApplyK(Option<Kont>, Value),
 }
 
-impl Display for State {
+impl Display for Config {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            State::Eval(t, k) => {
+            Config::Eval(t, k) => {
                 write!(f, "State: Eval\nTerm: [{}]\n", t)?;
                 match k {
                     None => write!(f, "Kont: ()"),
                     Some(k) => write!(f, "Kont: {}", k),
                 }
             }
-            State::ApplyT(v, t, k) => {
+            Config::ApplyT(v, t, k) => {
                 write!(f, "State: ApplyT\nValue: {}\nTerm: [{}]\n", v, t)?;
                 match k {
                     None => write!(f, "Kont: ()"),
                     Some(k) => write!(f, "Kont: {}", k),
                 }
             }
-            State::ApplyV(v, w, k) => {
+            Config::ApplyV(v, w, k) => {
                 write!(f, "State: ApplyV\nValue: {}\nWalue: {}\n", v, w)?;
                 match k {
                     None => write!(f, "Kont: ()"),
                     Some(k) => write!(f, "Kont: {}", k),
                 }
             }
-            State::ApplyK(k, v) => {
+            Config::ApplyK(k, v) => {
                 write!(f, "State: ApplyK\nValue: {}\n", v)?;
                 match k {
                     None => write!(f, "Kont: ()"),
@@ -125,115 +286,590 @@ impl Display for State {
     }
 }
 
-fn eval(t: Term, k: Option<Kont>) -> State {
+fn eval(t: Term, k: Option<Kont>) -> Config {
     match t {
-        Term::I => State::ApplyK(k, Value::I0),
-        Term::S => State::ApplyK(k, Value::S0),
-        Term::K => State::ApplyK(k, Value::K0),
-        Term::V => State::ApplyK(k, Value::V0),
-        Term::D => State::ApplyK(k, Value::D0),
-        Term::C => State::ApplyK(k, Value::C0),
-        Term::R => State::ApplyK(k, Value::Put0('\n')),
-        Term::Put(c) => State::ApplyK(k, Value::Put0(c)),
-        Term::App(t0, t1) => State::Eval(*t0, Some(Kont::BindT(t1, Box::new(k)))),
+        Term::I => Config::ApplyK(k, Value::I0),
+        Term::S => Config::ApplyK(k, Value::S0),
+        Term::K => Config::ApplyK(k, Value::K0),
+        Term::V => Config::ApplyK(k, Value::V0),
+        Term::D => Config::ApplyK(k, Value::D0),
+        Term::C => Config::ApplyK(k, Value::C0),
+        Term::R => Config::ApplyK(k, Value::Put0('\n')),
+        Term::Put(c) => Config::ApplyK(k, Value::Put0(c)),
+        Term::App(t0, t1) => Config::Eval(
+            Arc::unwrap_or_clone(t0),
+            Some(Kont::BindT(Box::new(Arc::unwrap_or_clone(t1)), Box::new(k))),
+        ),
     }
 }
 
-fn apply_t(v: Value, t: Term, k: Option<Kont>) -> State {
+fn apply_t(v: Value, t: Term, k: Option<Kont>) -> Config {
     match v {
-        Value::D0 => State::ApplyK(k, Value::D1T(Box::new(t))),
-        _ => State::Eval(t, Some(Kont::BindV(Box::new(v), Box::new(k)))),
+        Value::D0 => Config::ApplyK(k, Value::D1T(Box::new(t))),
+        _ => Config::Eval(t, Some(Kont::BindV(Box::new(v), Box::new(k)))),
     }
 }
 
-fn apply_v(v: Value, w: Value, k: Option<Kont>) -> State {
+fn apply_v(v: Value, w: Value, k: Option<Kont>) -> Config {
     match v {
-        Value::I0 => State::ApplyK(k, w),
+        Value::I0 => Config::ApplyK(k, w),
         Value::Put0(c) => {
-            print!("{}", c);
-            std::io::stdout().flush().unwrap();
-            State::ApplyK(k, w)
+            output::put(c);
+            Config::ApplyK(k, w)
         }
-        Value::K0 => State::ApplyK(k, Value::K1(Box::new(w))),
-        Value::K1(w0) => State::ApplyK(k, *w0),
-        Value::V0 => State::ApplyK(k, Value::V0),
+        Value::K0 => Config::ApplyK(k, Value::K1(Box::new(w))),
+        Value::K1(w0) => Config::ApplyK(k, *w0),
+        Value::V0 => Config::ApplyK(k, Value::V0),
         // This clones the kontinuation. How can we avoid this?
-        Value::C0 => State::ApplyV(w, Value::C1(Box::new(k.clone())), k),
-        Value::C1(k1) => State::ApplyK(*k1, w),
-        Value::D0 => State::ApplyK(k, Value::D1V(Box::new(w))),
-        Value::D1T(t0) => State::Eval(*t0, Some(Kont::BindW(Box::new(w), Box::new(k)))),
-        Value::D1V(v0) => State::ApplyV(*v0, w, k),
-        Value::S0 => State::ApplyK(k, Value::S1(Box::new(w))),
-        Value::S1(v0) => State::ApplyK(k, Value::S2(v0, Box::new(w))),
+        Value::C0 => Config::ApplyV(w, Value::C1(Box::new(k.clone())), k),
+        Value::C1(k1) => Config::ApplyK(*k1, w),
+        Value::D0 => Config::ApplyK(k, Value::D1V(Box::new(w))),
+        Value::D1T(t0) => Config::Eval(*t0, Some(Kont::BindW(Box::new(w), Box::new(k)))),
+        Value::D1V(v0) => Config::ApplyV(*v0, w, k),
+        Value::S0 => Config::ApplyK(k, Value::S1(Box::new(w))),
+        Value::S1(v0) => Config::ApplyK(k, Value::S2(v0, Box::new(w))),
         Value::S2(v0, v1) => {
             // This copys the third value. A tree clone! Very inefficient.
             // How do we share? Rc? Cow? Make a flat list or something?
-            State::ApplyV(*v0, w.clone(), Some(Kont::SWait(v1, Box::new(w), Box::new(k))))
+            Config::ApplyV(
+                *v0,
+                w.clone(),
+                Some(Kont::SWait(v1, Box::new(w), Box::new(k))),
+            )
         }
     }
 }
 
-fn apply_k(k: Kont, w: Value) -> State {
+fn apply_k(k: Kont, w: Value) -> Config {
     match k {
-        Kont::BindT(t, k) => State::ApplyT(w, *t, *k),
-        Kont::BindV(v, k) => State::ApplyV(*v, w, *k),
-        Kont::BindW(w1, k) => State::ApplyV(w, *w1, *k),
-        Kont::SWait(v1, v, k) => State::ApplyV(*v1, *v, Some(Kont::BindV(Box::new(w), k))),
+        Kont::BindT(t, k) => Config::ApplyT(w, *t, *k),
+        Kont::BindV(v, k) => Config::ApplyV(*v, w, *k),
+        Kont::BindW(w1, k) => Config::ApplyV(w, *w1, *k),
+        Kont::SWait(v1, v, k) => Config::ApplyV(*v1, *v, Some(Kont::BindV(Box::new(w), k))),
+    }
+}
+
+pub struct State {
+    config: Config,
+    steps: u64,
+    depth: usize, // frames in the continuation
+    stats: Stats,
+}
+
+impl Display for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.config)?;
+        write!(f, "Steps: {}", self.steps)
+    }
+}
+
+impl Render for State {
+    fn render(&self, r: &mut Renderer) {
+        let (flag, v, t, w, k) = match &self.config {
+            Config::Eval(t, k) => ("Eval", None, Some(t), None, k),
+            Config::ApplyT(v, t, k) => ("ApplyT", Some(v), Some(t), None, k),
+            Config::ApplyV(v, w, k) => ("ApplyV", Some(v), None, Some(w), k),
+            Config::ApplyK(k, v) => ("ApplyK", Some(v), None, None, k),
+        };
+        r.text("State: ");
+        r.text(flag);
+        r.newline();
+        if let Some(v) = v {
+            r.text("Value: ");
+            r.part(v);
+            r.newline();
+        }
+        if let Some(t) = t {
+            r.text("Term: [");
+            r.part(t);
+            r.char(']');
+            r.newline();
+        }
+        if let Some(w) = w {
+            r.text("Walue: ");
+            r.part(w);
+            r.newline();
+        }
+        r.text("Kont: ");
+        match k {
+            Some(k) => r.part(k),
+            None => r.text("()"),
+        }
+        r.newline();
+        r.text(&format!("Steps: {}", self.steps));
     }
 }
 
 pub fn new(t: Term) -> State {
-    State::Eval(t, None)
+    State {
+        config: Config::Eval(t, None),
+        steps: 0,
+        depth: 0,
+        stats: Stats::default(),
+    }
 }
 
 impl State {
-    pub fn step(self) -> Result<Self, Value> {
-        match self {
-            State::Eval(t, k) => Ok(eval(t, k)),
-            State::ApplyT(v, t, k) => Ok(apply_t(v, t, k)),
-            State::ApplyV(v, w, k) => Ok(apply_v(v, w, k)),
-            State::ApplyK(Some(k), w) => Ok(apply_k(k, w)),
-            State::ApplyK(None, v) => Err(v),
+    pub fn step(mut self) -> Result<Self, Value> {
+        self.count();
+        let config = match self.config {
+            Config::Eval(t, k) => eval(t, k),
+            Config::ApplyT(v, t, k) => apply_t(v, t, k),
+            Config::ApplyV(v, w, k) => apply_v(v, w, k),
+            Config::ApplyK(Some(k), w) => apply_k(k, w),
+            Config::ApplyK(None, v) => return Err(v),
+        };
+        Ok(State {
+            config,
+            steps: self.steps + 1,
+            depth: self.depth,
+            stats: self.stats,
+        })
+    }
+
+    // Counts the transition about to be taken from the current configuration
+    fn count(&mut self) {
+        let (flag, pushed) = match &self.config {
+            Config::Eval(Term::App(..), _) => (0, Some(stats::BIND_T)),
+            Config::Eval(..) => (0, None),
+            Config::ApplyT(Value::D0, _, _) => (1, None),
+            Config::ApplyT(..) => (1, Some(stats::BIND_V)),
+            Config::ApplyV(v, _, _) => {
+                self.stats.applications[v.kind().index()] += 1;
+                match v {
+                    Value::Put0(_) => self.stats.output += 1,
+                    Value::C1(k) => self.depth = depth(k),
+                    _ => (),
+                }
+                let pushed = match v {
+                    Value::D1T(_) => Some(stats::BIND_W),
+                    Value::S2(..) => Some(stats::S_WAIT),
+                    _ => None,
+                };
+                (2, pushed)
+            }
+            Config::ApplyK(Some(k), _) => {
+                self.depth -= 1;
+                let pushed = match k {
+                    Kont::SWait(..) => Some(stats::BIND_V),
+                    _ => None,
+                };
+                (3, pushed)
+            }
+            Config::ApplyK(None, _) => return,
+        };
+        self.stats.transitions[flag] += 1;
+        if let Some(frame) = pushed {
+            self.depth += 1;
+            self.stats.frames[frame] += 1;
         }
+        self.stats.peak_depth = self.stats.peak_depth.max(self.depth);
     }
 
-    pub fn run(self) -> Value {
+    /// What the machine has done so far.
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Number of transitions taken so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    fn finished(&self) -> bool {
+        matches!(self.config, Config::ApplyK(None, _))
+    }
+
+    /// Runs until the program finishes or `stop` holds for the state about
+    /// to be stepped.
+    pub fn run_until(self, mut stop: impl FnMut(&Self) -> bool) -> Outcome<Self, Value> {
         let mut state = self;
         loop {
+            if !state.finished() && stop(&state) {
+                return Outcome::Suspended(state);
+            }
             match state.step() {
                 Ok(s) => state = s,
-                Err(v) => return v,
+                Err(v) => return Outcome::Finished(v),
             }
         }
     }
-    // todo: add a repl
-}
 
-pub fn main(term: Term, interactive: bool) {
-    let state = new(term);
+    /// Runs for at most `steps` more transitions.
+    pub fn run_for(self, steps: u64) -> Outcome<Self, Value> {
+        let end = self.steps + steps;
+        self.run_until(|s| s.steps >= end)
+    }
 
-    if interactive {
-        let mut state = state;
-        println!("{}", state);
-        println!("Press enter to step, or Ctrl-C to exit. `r` to run to completion.");
-        let result = loop {
-            print!("> ");
-            std::io::stdout().flush().unwrap();
-            let mut input = String::new();
-            std::io::stdin().read_line(&mut input).unwrap();
-            match input.trim() {
-                "r" => break state.run(),
-                _ => (),
+    pub fn run(self) -> Value {
+        self.finish().0
+    }
+
+    /// Runs to the end, returning the value along with what the run took.
+    pub fn finish(self) -> (Value, Stats) {
+        let mut state = self;
+        loop {
+            if let Config::ApplyK(None, v) = state.config {
+                return (v, state.stats);
             }
-            match state.step() {
-                Ok(s) => state = s,
-                Err(v) => break v,
+            state = state.step().ok().unwrap();
+        }
+    }
+
+    /// Like `finish`, calling `poll` once every `every` steps and giving the
+    /// state back when it gives false.
+    pub fn finish_polling(
+        self,
+        every: u64,
+        mut poll: impl FnMut(&Self) -> bool,
+    ) -> Outcome<Self, (Value, Stats)> {
+        let every = every.max(1);
+        let mut state = self;
+        loop {
+            if let Config::ApplyK(None, v) = state.config {
+                return Outcome::Finished((v, state.stats));
             }
-            println!("{}", state);
+            state = state.step().ok().unwrap();
+            if state.steps.is_multiple_of(every) && !poll(&state) {
+                return Outcome::Suspended(state);
+            }
+        }
+    }
+
+    /// A one-line account of where the machine is.
+    pub fn summary(&self) -> String {
+        let bounds = Bounds::SHORT.narrow(70);
+        let (flag, current) = match &self.config {
+            Config::Eval(t, _) => ("Eval", format!("evaluating [{}]", bounds.render(t))),
+            Config::ApplyT(v, _, _) => ("ApplyT", format!("holding {}", bounds.render(v))),
+            Config::ApplyV(v, _, _) => ("ApplyV", format!("holding {}", bounds.render(v))),
+            Config::ApplyK(_, v) => ("ApplyK", format!("holding {}", bounds.render(v))),
+        };
+        format!(
+            "after {} steps, {}, {} frames deep, {}",
+            self.steps, flag, self.depth, current
+        )
+    }
+
+    /// The state written down. Nothing is shared in this machine, so
+    /// nothing is written twice by sharing either, except terms.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut builder = Builder::new(None);
+        let mut id = |part| add_part(&mut builder, part);
+        let config = match &self.config {
+            Config::Eval(t, k) => {
+                let k = k.as_ref().map(|k| id(Part::Kont(k)));
+                snapshot::Config::Eval(builder.term(t), k)
+            }
+            Config::ApplyT(v, t, k) => {
+                let v = id(Part::Value(v));
+                let k = k.as_ref().map(|k| id(Part::Kont(k)));
+                snapshot::Config::ApplyT(v, builder.term(t), k)
+            }
+            Config::ApplyV(v, w, k) => {
+                let v = id(Part::Value(v));
+                let w = id(Part::Value(w));
+                let k = k.as_ref().map(|k| id(Part::Kont(k)));
+                snapshot::Config::ApplyV(v, w, k)
+            }
+            Config::ApplyK(k, v) => {
+                let k = k.as_ref().map(|k| id(Part::Kont(k)));
+                snapshot::Config::ApplyK(k, id(Part::Value(v)))
+            }
+        };
+        builder.finish(config, self.steps)
+    }
+}
+
+// A value or frame being written to a snapshot
+#[derive(Clone, Copy)]
+enum Part<'a> {
+    Value(&'a Value),
+    Kont(&'a Kont),
+}
+
+impl Part<'_> {
+    fn address(self) -> usize {
+        match self {
+            Part::Value(v) => v as *const Value as usize,
+            Part::Kont(k) => k as *const Kont as usize,
+        }
+    }
+}
+
+// Adds a value or frame and all it holds to a snapshot, parts first
+fn add_part(builder: &mut Builder, part: Part) -> snapshot::Id {
+    let mut todo = vec![(part, false)];
+    while let Some((part, ready)) = todo.pop() {
+        if builder.get(part.address()).is_some() {
+            continue;
+        }
+        if !ready {
+            todo.push((part, true));
+            match part {
+                Part::Value(v) => match v {
+                    Value::S1(x) | Value::K1(x) | Value::D1V(x) => {
+                        todo.push((Part::Value(x), false))
+                    }
+                    Value::S2(x, y) => {
+                        todo.push((Part::Value(y), false));
+                        todo.push((Part::Value(x), false));
+                    }
+                    Value::C1(k) => {
+                        if let Some(k) = k.as_ref() {
+                            todo.push((Part::Kont(k), false));
+                        }
+                    }
+                    _ => {}
+                },
+                Part::Kont(k) => {
+                    if let Some(next) = k.next() {
+                        todo.push((Part::Kont(next), false));
+                    }
+                    match k {
+                        Kont::BindV(v, _) | Kont::BindW(v, _) => todo.push((Part::Value(v), false)),
+                        Kont::SWait(v1, v, _) => {
+                            todo.push((Part::Value(v), false));
+                            todo.push((Part::Value(v1), false));
+                        }
+                        Kont::BindT(..) => {}
+                    }
+                }
+            }
+            continue;
+        }
+        let value = |v: &Value| builder.get(Part::Value(v).address()).unwrap();
+        let frame = |k: &Option<Kont>| {
+            k.as_ref()
+                .map(|k| builder.get(Part::Kont(k).address()).unwrap())
         };
-        println!("-----\nResult:\n{}", result);
+        let node = match part {
+            Part::Value(v) => Node::Value(match v {
+                Value::I0 => snapshot::Value::I0,
+                Value::S0 => snapshot::Value::S0,
+                Value::K0 => snapshot::Value::K0,
+                Value::V0 => snapshot::Value::V0,
+                Value::D0 => snapshot::Value::D0,
+                Value::C0 => snapshot::Value::C0,
+                Value::Put0(c) => snapshot::Value::Put0(*c),
+                Value::S1(x) => snapshot::Value::S1(value(x)),
+                Value::S2(x, y) => snapshot::Value::S2(value(x), value(y)),
+                Value::K1(x) => snapshot::Value::K1(value(x)),
+                Value::D1V(x) => snapshot::Value::D1V(value(x)),
+                Value::C1(k) => snapshot::Value::C1(frame(k)),
+                Value::D1T(t) => snapshot::Value::D1T(builder.term(t)),
+            }),
+            Part::Kont(k) => Node::Kont(match k {
+                Kont::BindV(v, next) => snapshot::Kont::BindV(value(v), frame(next)),
+                Kont::BindW(w, next) => snapshot::Kont::BindW(value(w), frame(next)),
+                Kont::SWait(v1, v, next) => snapshot::Kont::SWait(value(v1), value(v), frame(next)),
+                Kont::BindT(t, next) => {
+                    let next = frame(next);
+                    snapshot::Kont::BindT(builder.term(t), next)
+                }
+            }),
+        };
+        builder.add(part.address(), node);
+    }
+    builder.get(part.address()).unwrap()
+}
+
+/// A machine in the state a snapshot was taken in, with shared values
+/// copied. Terms written as positions are taken from `program`, which
+/// should be the one the snapshot came from.
+pub fn from_snapshot(
+    snapshot: &Snapshot,
+    program: Option<&Program>,
+) -> Result<State, snapshot::Error> {
+    let terms = snapshot.terms(program)?;
+    let term = |id: snapshot::Id| terms[id].as_deref().unwrap().clone();
+    // how many times each node is held, so that its last holder can take it
+    // rather than copy it
+    let mut holders = vec![0; snapshot.nodes.len()];
+    let mut hold = |ids: &[Option<snapshot::Id>]| {
+        for id in ids.iter().flatten() {
+            holders[*id] += 1;
+        }
+    };
+    for node in &snapshot.nodes {
+        match *node {
+            Node::Value(v) => match v {
+                snapshot::Value::S1(x) | snapshot::Value::K1(x) | snapshot::Value::D1V(x) => {
+                    hold(&[Some(x)])
+                }
+                snapshot::Value::S2(x, y) => hold(&[Some(x), Some(y)]),
+                snapshot::Value::C1(k) => hold(&[k]),
+                _ => {}
+            },
+            Node::Kont(k) => match k {
+                snapshot::Kont::BindT(_, k) => hold(&[k]),
+                snapshot::Kont::BindV(v, k) | snapshot::Kont::BindW(v, k) => hold(&[Some(v), k]),
+                snapshot::Kont::SWait(v1, v, k) => hold(&[Some(v1), Some(v), k]),
+            },
+            _ => {}
+        }
+    }
+    match snapshot.config {
+        snapshot::Config::Eval(_, k) => hold(&[k]),
+        snapshot::Config::ApplyT(v, _, k) => hold(&[Some(v), k]),
+        snapshot::Config::ApplyV(v, w, k) => hold(&[Some(v), Some(w), k]),
+        snapshot::Config::ApplyK(k, v) => hold(&[k, Some(v)]),
+    }
+
+    let mut values: Vec<Option<Value>> = vec![None; snapshot.nodes.len()];
+    let mut konts: Vec<Option<Kont>> = vec![None; snapshot.nodes.len()];
+    for (id, node) in snapshot.nodes.iter().enumerate() {
+        let mut value = |x| Box::new(take(&mut values, &mut holders, x));
+        match *node {
+            Node::Value(v) => {
+                let v = match v {
+                    snapshot::Value::I0 => Value::I0,
+                    snapshot::Value::S0 => Value::S0,
+                    snapshot::Value::K0 => Value::K0,
+                    snapshot::Value::V0 => Value::V0,
+                    snapshot::Value::D0 => Value::D0,
+                    snapshot::Value::C0 => Value::C0,
+                    snapshot::Value::Put0(c) => Value::Put0(c),
+                    snapshot::Value::S1(x) => Value::S1(value(x)),
+                    snapshot::Value::S2(x, y) => Value::S2(value(x), value(y)),
+                    snapshot::Value::K1(x) => Value::K1(value(x)),
+                    snapshot::Value::D1T(t) => Value::D1T(Box::new(term(t))),
+                    snapshot::Value::D1V(x) => Value::D1V(value(x)),
+                    snapshot::Value::C1(k) => {
+                        Value::C1(Box::new(k.map(|k| take(&mut konts, &mut holders, k))))
+                    }
+                };
+                values[id] = Some(v);
+            }
+            Node::Kont(k) => {
+                let (k, next) = match k {
+                    snapshot::Kont::BindT(t, next) => {
+                        (Kont::BindT(Box::new(term(t)), Box::default()), next)
+                    }
+                    snapshot::Kont::BindV(v, next) => (Kont::BindV(value(v), Box::default()), next),
+                    snapshot::Kont::BindW(w, next) => (Kont::BindW(value(w), Box::default()), next),
+                    snapshot::Kont::SWait(v1, v, next) => {
+                        (Kont::SWait(value(v1), value(v), Box::default()), next)
+                    }
+                };
+                let next = next.map(|next| take(&mut konts, &mut holders, next));
+                konts[id] = Some(k.on(next));
+            }
+            _ => {}
+        }
+    }
+
+    let mut value = |x| take(&mut values, &mut holders, x);
+    let config = match snapshot.config {
+        snapshot::Config::Eval(t, k) => {
+            Config::Eval(term(t), k.map(|k| take(&mut konts, &mut holders, k)))
+        }
+        snapshot::Config::ApplyT(v, t, k) => {
+            let v = value(v);
+            Config::ApplyT(v, term(t), k.map(|k| take(&mut konts, &mut holders, k)))
+        }
+        snapshot::Config::ApplyV(v, w, k) => {
+            let (v, w) = (value(v), value(w));
+            Config::ApplyV(v, w, k.map(|k| take(&mut konts, &mut holders, k)))
+        }
+        snapshot::Config::ApplyK(k, v) => {
+            let v = value(v);
+            Config::ApplyK(k.map(|k| take(&mut konts, &mut holders, k)), v)
+        }
+    };
+    let k = match &config {
+        Config::Eval(_, k)
+        | Config::ApplyT(_, _, k)
+        | Config::ApplyV(_, _, k)
+        | Config::ApplyK(k, _) => k,
+    };
+    Ok(State {
+        depth: depth(k),
+        config,
+        steps: snapshot.steps,
+        stats: Stats::default(),
+    })
+}
+
+// Takes a node for its last holder, or copies it for the others
+fn take<T: Clone>(nodes: &mut [Option<T>], holders: &mut [usize], id: snapshot::Id) -> T {
+    holders[id] -= 1;
+    match holders[id] {
+        0 => nodes[id].take().unwrap(),
+        _ => nodes[id].clone().unwrap(),
+    }
+}
+
+// Steps through the program by hand, from any state
+fn step_through(mut state: State, bounds: Bounds) -> (Value, Stats) {
+    println!("{}", bounds.render(&state));
+    println!("Press enter to step, or Ctrl-C to exit. `r` to run to completion.");
+    loop {
+        print!("> ");
+        std::io::stdout().flush().unwrap();
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input).unwrap();
+        if input.trim() == "r" || state.finished() {
+            break state.finish();
+        }
+        state = state.step().ok().unwrap();
+        println!("{}", bounds.render(&state));
+    }
+}
+
+pub fn main(mut state: State, options: &Options) {
+    let stats = if options.interactive {
+        let result = step_through(state, options.bounds);
+        println!("-----");
+        print_result(&result.0, options, Value::readback);
+        result.1
     } else {
-        let result = state.run();
-        println!("Result:\n{}", result);
+        let interrupts = Interrupts::install()
+            .inspect_err(|e| eprintln!("Could not handle Ctrl-C: {}", e))
+            .ok();
+        loop {
+            let stopped = match &interrupts {
+                Some(interrupts) => {
+                    state.finish_polling(interrupt::POLL, |_| !interrupts.pending())
+                }
+                None => Outcome::Finished(state.finish()),
+            };
+            state = match stopped {
+                Outcome::Finished((result, stats)) => {
+                    output::flush();
+                    print_result(&result, options, Value::readback);
+                    break stats;
+                }
+                Outcome::Suspended(state) | Outcome::Exceeded(_, state) => state,
+            };
+            let interrupts = interrupts
+                .as_ref()
+                .expect("only a signal stops a run early");
+            if interrupts.take_report() {
+                eprintln!("\n{}", state.summary());
+                if !interrupts.pending() {
+                    continue;
+                }
+            }
+            eprintln!("\nInterrupted {}", state.summary());
+            match interrupts.ask(options.snapshot.is_some()) {
+                Choice::Resume => {}
+                Choice::Inspect => {
+                    output::set(Flush::None);
+                    let result = step_through(state, options.bounds);
+                    println!("-----");
+                    print_result(&result.0, options, Value::readback);
+                    break result.1;
+                }
+                Choice::Save => {
+                    save(&state.snapshot(), options.snapshot.as_ref().unwrap());
+                    std::process::exit(130)
+                }
+                Choice::Quit => std::process::exit(130),
+            }
+        }
+    };
+    if let Some(format) = options.stats {
+        eprintln!("{}", stats.format(format));
     }
-}
\ No newline at end of file
+}
@@ -1,15 +1,199 @@
-use crate::term::Term;
+use super::coverage::Coverage;
+use super::debug::Debugger;
+use super::dot;
+use super::flame::Flamegraph;
+use super::interrupt::{self, Choice, Interrupts};
+use super::output::{self, Flush};
+use super::profile::Profile;
+use super::provenance::Provenance;
+use super::readback::{readback, Readback, Shape};
+use super::render::{Bounds, Frame, Render, Renderer};
+use super::snapshot::{self, save, Builder, Node, Snapshot};
+use super::stats::{self, Stats, ValueKind};
+use super::trace::Trace;
+use super::{print_result, truncate, Limit, Limits, Options, Outcome};
+use crate::term::{Program, Term};
 use std::char;
-use std::fmt::Display;
-use std::io::Write;
+use std::collections::HashSet;
+use std::fmt::{Debug, Display};
+use std::fs::File;
+use std::io::BufWriter;
+use std::marker::PhantomData;
+use std::mem::{replace, take};
+use std::ops::Deref;
+use std::path::Path;
 use std::rc::Rc;
-use std::mem::take;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
 
 // A sharing abstract machine for unlambda
 // I hope this runs f..a..s..t..
 
-#[derive(Debug, Clone)]
-pub enum Value<'a> {
+/// How the machine shares values and continuations between states.
+pub trait Share: Sized {
+    type Ptr<T>: Deref<Target = T> + AsRef<T> + Clone;
+    /// What a state counts its live values with; nothing unless `COUNTED`
+    type Counter: Default + Clone;
+    /// Whether live values are counted
+    const COUNTED: bool = false;
+    /// Whether stats are kept
+    const STATS: bool = false;
+
+    fn new<T>(value: T) -> Self::Ptr<T>;
+    fn get_mut<T>(this: &mut Self::Ptr<T>) -> Option<&mut T>;
+
+    /// Like `new`, for a value counted by `counter` as live until its last
+    /// pointer is dropped.
+    fn counted<T>(value: T, _counter: &Self::Counter) -> Self::Ptr<T> {
+        Self::new(value)
+    }
+
+    /// Number of values counted by `counter` that are still alive.
+    fn live(_counter: &Self::Counter) -> usize {
+        0
+    }
+}
+
+/// Sharing through `Rc`. Stays on one thread.
+#[derive(Debug, Clone, Copy)]
+pub enum Local {}
+
+impl Share for Local {
+    type Ptr<T> = Rc<T>;
+    type Counter = ();
+
+    fn new<T>(value: T) -> Rc<T> {
+        Rc::new(value)
+    }
+
+    fn get_mut<T>(this: &mut Rc<T>) -> Option<&mut T> {
+        Rc::get_mut(this)
+    }
+}
+
+/// Sharing through `Arc`. A `State<Shared>` is `Send`, so it can be started
+/// on one thread and resumed on another.
+#[derive(Debug, Clone, Copy)]
+pub enum Shared {}
+
+impl Share for Shared {
+    type Ptr<T> = Arc<T>;
+    type Counter = ();
+
+    fn new<T>(value: T) -> Arc<T> {
+        Arc::new(value)
+    }
+
+    fn get_mut<T>(this: &mut Arc<T>) -> Option<&mut T> {
+        Arc::get_mut(this)
+    }
+}
+
+/// Another kind of sharing, with live values counted as they are allocated
+/// and freed. Value limits need this; it is opt-in because counting costs a
+/// little on every allocation.
+#[derive(Debug, Clone, Copy)]
+pub struct Counted<P>(PhantomData<P>);
+
+/// A pointer of a `Counted` machine. Values are counted by the state that
+/// made them, wherever they are freed.
+pub struct CountedPtr<P: Share, T>(P::Ptr<Live<T>>);
+
+struct Live<T> {
+    value: T,
+    // the counter of the state that made this value, if it is one
+    counter: Option<Arc<AtomicUsize>>,
+}
+
+impl<T> Drop for Live<T> {
+    fn drop(&mut self) {
+        if let Some(counter) = &self.counter {
+            counter.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<P: Share, T> Deref for CountedPtr<P, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0.value
+    }
+}
+
+impl<P: Share, T> AsRef<T> for CountedPtr<P, T> {
+    fn as_ref(&self) -> &T {
+        &self.0.value
+    }
+}
+
+impl<P: Share, T> Clone for CountedPtr<P, T> {
+    fn clone(&self) -> Self {
+        CountedPtr(self.0.clone())
+    }
+}
+
+impl<P: Share> Share for Counted<P> {
+    type Ptr<T> = CountedPtr<P, T>;
+    type Counter = Arc<AtomicUsize>;
+    const COUNTED: bool = true;
+    const STATS: bool = P::STATS;
+
+    fn new<T>(value: T) -> CountedPtr<P, T> {
+        CountedPtr(P::new(Live {
+            value,
+            counter: None,
+        }))
+    }
+
+    fn get_mut<T>(this: &mut CountedPtr<P, T>) -> Option<&mut T> {
+        P::get_mut(&mut this.0).map(|live| &mut live.value)
+    }
+
+    fn counted<T>(value: T, counter: &Arc<AtomicUsize>) -> CountedPtr<P, T> {
+        counter.fetch_add(1, Ordering::Relaxed);
+        CountedPtr(P::new(Live {
+            value,
+            counter: Some(counter.clone()),
+        }))
+    }
+
+    fn live(counter: &Arc<AtomicUsize>) -> usize {
+        counter.load(Ordering::Relaxed)
+    }
+}
+
+/// Another kind of sharing, keeping stats on what the machine does, and a
+/// profile, coverage and output provenance if asked for. Opt-in for the same reason as `Counted`.
+#[derive(Debug, Clone, Copy)]
+pub struct Tallied<P>(PhantomData<P>);
+
+impl<P: Share> Share for Tallied<P> {
+    type Ptr<T> = P::Ptr<T>;
+    type Counter = P::Counter;
+    const COUNTED: bool = P::COUNTED;
+    const STATS: bool = true;
+
+    fn new<T>(value: T) -> P::Ptr<T> {
+        P::new(value)
+    }
+
+    fn get_mut<T>(this: &mut P::Ptr<T>) -> Option<&mut T> {
+        P::get_mut(this)
+    }
+
+    fn counted<T>(value: T, counter: &P::Counter) -> P::Ptr<T> {
+        P::counted(value, counter)
+    }
+
+    fn live(counter: &P::Counter) -> usize {
+        P::live(counter)
+    }
+}
+
+#[derive(Clone)]
+pub enum Value<P: Share> {
     I0,
     S0,
     K0,
@@ -17,15 +201,16 @@ pub enum Value<'a> {
     D0,
     C0,
     Put0(char),
-    S1(Rc<Value<'a>>),
-    S2(Rc<Value<'a>>, Rc<Value<'a>>),
-    K1(Rc<Value<'a>>),
-    D1T(&'a Term),
-    D1V(Rc<Value<'a>>),
-    C1(Option<Rc<Kont<'a>>>),
+    S1(P::Ptr<Value<P>>),
+    S2(P::Ptr<Value<P>>, P::Ptr<Value<P>>),
+    K1(P::Ptr<Value<P>>),
+    D1T(Arc<Term>),
+    D1V(P::Ptr<Value<P>>),
+    /// A continuation, and the number of frames in it
+    C1(Option<P::Ptr<Kont<P>>>, usize),
 }
 
-impl Display for Value<'_> {
+impl<P: Share> Display for Value<P> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Value::I0 => write!(f, "i"),
@@ -41,51 +226,235 @@ impl Display for Value<'_> {
                     write!(f, ".{}", c)
                 }
             }
-            Value::S1(w) => write!(f, "`s{}", w),
-            Value::S2(w0, w1) => write!(f, "``s{}{}", w0, w1),
-            Value::K1(w) => write!(f, "`k{}", w),
-            Value::D1T(t) => write!(f, "`d[{}]", t),
-            Value::D1V(t) => write!(f, "`d{}", t),
-            Value::C1(k) => match k.as_ref() {
-                Some(k) => write!(f, "`c({})", k),
+            Value::S1(w) => write!(f, "`s{}", w.as_ref()),
+            Value::S2(w0, w1) => write!(f, "``s{}{}", w0.as_ref(), w1.as_ref()),
+            Value::K1(w) => write!(f, "`k{}", w.as_ref()),
+            Value::D1T(t) => write!(f, "`d[{}]", t.deref()),
+            Value::D1V(t) => write!(f, "`d{}", t.as_ref()),
+            Value::C1(k, _) => match k.as_ref() {
+                Some(k) => write!(f, "`c({})", k.as_ref()),
                 None => write!(f, "`c()"),
             },
         }
     }
 }
 
-#[derive(Debug, Clone)]
-pub enum Kont<'a> {
-    BindT(&'a Term, Option<Rc<Kont<'a>>>),
-    BindV(Rc<Value<'a>>, Option<Rc<Kont<'a>>>),
-    BindW(Rc<Value<'a>>, Option<Rc<Kont<'a>>>),
-    SWait(Rc<Value<'a>>, Rc<Value<'a>>, Option<Rc<Kont<'a>>>),
+impl<P: Share> Render for Value<P> {
+    fn render(&self, r: &mut Renderer) {
+        match self {
+            Value::S1(w) => {
+                r.text("`s");
+                render_value::<P>(r, w);
+            }
+            Value::S2(w0, w1) => {
+                r.text("``s");
+                render_value::<P>(r, w0);
+                render_value::<P>(r, w1);
+            }
+            Value::K1(w) => {
+                r.text("`k");
+                render_value::<P>(r, w);
+            }
+            Value::D1T(t) => {
+                r.text("`d[");
+                r.part(t.as_ref());
+                r.char(']');
+            }
+            Value::D1V(w) => {
+                r.text("`d");
+                render_value::<P>(r, w);
+            }
+            Value::C1(k, _) => {
+                r.text("`c(");
+                if let Some(k) = k {
+                    r.part(k.as_ref());
+                }
+                r.char(')');
+            }
+            // atoms print the same either way
+            v => r.text(&v.to_string()),
+        }
+    }
 }
-impl Display for Kont<'_> {
+
+impl<P: Share> Value<P> {
+    /// What kind of value this is, as counted in stats.
+    pub fn kind(&self) -> ValueKind {
+        match self {
+            Value::I0 => ValueKind::I0,
+            Value::S0 => ValueKind::S0,
+            Value::K0 => ValueKind::K0,
+            Value::V0 => ValueKind::V0,
+            Value::D0 => ValueKind::D0,
+            Value::C0 => ValueKind::C0,
+            Value::Put0(_) => ValueKind::Put0,
+            Value::S1(_) => ValueKind::S1,
+            Value::S2(..) => ValueKind::S2,
+            Value::K1(_) => ValueKind::K1,
+            Value::D1T(_) => ValueKind::D1T,
+            Value::D1V(_) => ValueKind::D1V,
+            Value::C1(..) => ValueKind::C1,
+        }
+    }
+
+    /// A term that evaluates to this value, or `None` if it holds a
+    /// continuation. Shared values are shared in the term too.
+    pub fn readback(&self) -> Option<Arc<Term>> {
+        readback(self)
+    }
+}
+
+impl<P: Share> Readback for Value<P> {
+    fn shape(&self) -> Shape<'_, Self> {
+        match self {
+            Value::I0 => Shape::Atom(Term::I),
+            Value::S0 => Shape::Atom(Term::S),
+            Value::K0 => Shape::Atom(Term::K),
+            Value::V0 => Shape::Atom(Term::V),
+            Value::D0 => Shape::Atom(Term::D),
+            Value::C0 => Shape::Atom(Term::C),
+            Value::Put0(c) => Shape::Atom(Term::Put(*c)),
+            Value::S1(w) => Shape::S1(w.as_ref()),
+            Value::S2(w0, w1) => Shape::S2(w0.as_ref(), w1.as_ref()),
+            Value::K1(w) => Shape::K1(w.as_ref()),
+            Value::D1T(t) => Shape::D1T(t.clone()),
+            Value::D1V(w) => Shape::D1V(w.as_ref()),
+            Value::C1(..) => Shape::C1,
+        }
+    }
+}
+
+impl<P: Share> Debug for Value<P> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut wrapped = "()".to_string();
-        let mut current = self;
-        loop {
-            let (next, text) = match current {
-                Kont::BindT(t, k) => (k, format!("`{}[{}]", wrapped, t)),
-                Kont::BindV(v, k) => (k, format!("`{}{}", v, wrapped)),
-                Kont::BindW(w, k) => (k, format!("`{}{}", wrapped, w)),
-                Kont::SWait(v1, v, k) => (k, format!("`{}`{}{}", wrapped, v1, v)),
-            };
-            match next.as_ref() {
-                Some(k) => {
-                    wrapped = text;
-                    current = k;
-                }
-                None => {
-                    return write!(f, "{}", text);
-                }
+        Display::fmt(self, f)
+    }
+}
+
+// A value made by the state `counter` belongs to
+fn alloc<P: Share>(v: Value<P>, counter: &P::Counter) -> P::Ptr<Value<P>> {
+    P::counted(v, counter)
+}
+
+#[derive(Clone)]
+pub enum Kont<P: Share> {
+    BindT(Arc<Term>, Option<P::Ptr<Kont<P>>>),
+    BindV(P::Ptr<Value<P>>, Option<P::Ptr<Kont<P>>>),
+    BindW(P::Ptr<Value<P>>, Option<P::Ptr<Kont<P>>>),
+    SWait(P::Ptr<Value<P>>, P::Ptr<Value<P>>, Option<P::Ptr<Kont<P>>>),
+}
+impl<P: Share> Display for Kont<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // each frame wraps the ones above it, so the bottom one goes first
+        let frames: Vec<&Kont<P>> = self.frames().collect();
+        for frame in frames.iter().rev() {
+            match frame {
+                Kont::BindV(v, _) => write!(f, "`{}", v.as_ref())?,
+                _ => write!(f, "`")?,
+            }
+        }
+        write!(f, "()")?;
+        for frame in frames {
+            match frame {
+                Kont::BindT(t, _) => write!(f, "[{}]", t.deref())?,
+                Kont::BindV(..) => {}
+                Kont::BindW(w, _) => write!(f, "{}", w.as_ref())?,
+                Kont::SWait(v1, v, _) => write!(f, "`{}{}", v1.as_ref(), v.as_ref())?,
             }
         }
+        Ok(())
+    }
+}
+
+impl<P: Share> Render for Kont<P> {
+    fn render(&self, r: &mut Renderer) {
+        r.kont(self.frames());
+    }
+}
+
+impl<P: Share> Frame for Kont<P> {
+    fn before(&self, r: &mut Renderer) {
+        r.char('`');
+        if let Kont::BindV(v, _) = self {
+            render_value::<P>(r, v);
+        }
+    }
+
+    fn after(&self, r: &mut Renderer) {
+        match self {
+            Kont::BindT(t, _) => {
+                r.char('[');
+                r.part(t.as_ref());
+                r.char(']');
+            }
+            Kont::BindV(..) => {}
+            Kont::BindW(w, _) => render_value::<P>(r, w),
+            Kont::SWait(v1, v, _) => {
+                r.char('`');
+                render_value::<P>(r, v1);
+                render_value::<P>(r, v);
+            }
+        }
+    }
+}
+
+// Shows a value held by another, labelled if it is shared
+fn render_value<P: Share>(r: &mut Renderer, v: &P::Ptr<Value<P>>) {
+    match v.as_ref() {
+        Value::S1(_) | Value::S2(..) | Value::K1(_) | Value::D1V(_) | Value::C1(Some(_), _) => {
+            r.shared(value_address::<P>(v), v.as_ref())
+        }
+        v => r.part(v),
+    }
+}
+
+// Shows a continuation, its frames labelled if they are shared
+fn render_kont<P: Share>(r: &mut Renderer, k: &Option<P::Ptr<Kont<P>>>) {
+    match k {
+        Some(k) => r.part(k.as_ref()),
+        None => r.text("()"),
+    }
+}
+
+impl<P: Share> Kont<P> {
+    /// This frame and the ones below it, from the top.
+    pub fn frames(&self) -> impl Iterator<Item = &Kont<P>> {
+        std::iter::successors(Some(self), |k| k.next().as_deref())
+    }
+
+    /// The frame below this one.
+    pub fn next(&self) -> &Option<P::Ptr<Kont<P>>> {
+        match self {
+            Kont::BindT(_, k) | Kont::BindV(_, k) | Kont::BindW(_, k) | Kont::SWait(_, _, k) => k,
+        }
+    }
+}
+
+// Number of frames in a continuation
+fn depth<P: Share>(k: &Option<P::Ptr<Kont<P>>>) -> usize {
+    let mut depth = 0;
+    let mut current = k;
+    while let Some(k) = current {
+        depth += 1;
+        current = k.next();
+    }
+    depth
+}
+
+fn frame_address<P: Share>(k: &P::Ptr<Kont<P>>) -> usize {
+    k.as_ref() as *const Kont<P> as usize
+}
+
+fn value_address<P: Share>(v: &P::Ptr<Value<P>>) -> usize {
+    v.as_ref() as *const Value<P> as usize
+}
+
+impl<P: Share> Debug for Kont<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum StateFlag {
     Eval,
     ApplyT,
@@ -93,123 +462,195 @@ pub enum StateFlag {
     ApplyK,
 }
 
-#[derive(Debug, Clone)]
-pub struct State<'a> {
+#[derive(Clone)]
+pub struct State<P: Share> {
     flag: StateFlag,
-    t: Option<&'a Term>, // this may bite me in the ass later
-    v: Option<Rc<Value<'a>>>,
-    w: Option<Rc<Value<'a>>>,
-    k: Option<Rc<Kont<'a>>>,
+    t: Option<Arc<Term>>,
+    v: Option<P::Ptr<Value<P>>>,
+    w: Option<P::Ptr<Value<P>>>,
+    k: Option<P::Ptr<Kont<P>>>,
+    simplify: bool,
+    fuse: bool,
+    steps: u64,          // transitions actually taken
+    rules: u64,          // machine rules applied, counting each one a fused transition covers
+    depth: usize,        // frames in k
+    counter: P::Counter, // live values allocated by this machine
+    kept: usize,         // of those, the ones only its checkpoints hold, as last counted
+    output: u64,         // bytes printed
+    written: u64,        // bytes actually written, more than `output` after going back
+    limits: Limits,
+    deadline: Option<Instant>,
+    stats: Stats,
+    profile: Option<Box<Profile>>,
+    coverage: Option<Box<Coverage>>,
+    provenance: Option<Box<Provenance>>,
 }
 
-impl Display for State<'_> {
+impl<P: Share> Display for State<P> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self.flag {
             StateFlag::Eval => {
-                write!(f, "State: Eval\n")?;
+                writeln!(f, "State: Eval")?;
             }
             StateFlag::ApplyT => {
-                write!(f, "State: ApplyT\n")?;
+                writeln!(f, "State: ApplyT")?;
             }
             StateFlag::ApplyV => {
-                write!(f, "State: ApplyV\n")?;
+                writeln!(f, "State: ApplyV")?;
             }
             StateFlag::ApplyK => {
-                write!(f, "State: ApplyK\n")?;
+                writeln!(f, "State: ApplyK")?;
             }
         }
         if let Some(v) = &self.v {
-            write!(f, "Value: {}\n", v)?;
+            writeln!(f, "Value: {}", v.as_ref())?;
         }
         if let Some(t) = &self.t {
-            write!(f, "Term: [{}]\n", t)?;
+            writeln!(f, "Term: [{}]", t.deref())?;
         }
         if let Some(w) = &self.w {
-            write!(f, "Walue: {}\n", w)?;
+            writeln!(f, "Walue: {}", w.as_ref())?;
         }
         match &self.k {
-            None => write!(f, "Kont: ()"),
-            Some(k) => write!(f, "Kont: {}", k),
+            None => writeln!(f, "Kont: ()")?,
+            Some(k) => writeln!(f, "Kont: {}", k.as_ref())?,
         }
+        write!(f, "Steps: {} ({} rules)", self.steps, self.rules)
     }
 }
 
-fn eval<'a>(state: &mut State<'a>) {
-    let t = state.t.unwrap();
-    match t {
-        Term::I => {
-            state.flag = StateFlag::ApplyK;
-            state.v = Some(Rc::new(Value::I0));
-        }
-        Term::S => {
-            state.flag = StateFlag::ApplyK;
-            state.v = Some(Rc::new(Value::S0));
-        }
-        Term::K => {
-            state.flag = StateFlag::ApplyK;
-            state.v = Some(Rc::new(Value::K0));
-        }
-        Term::V => {
-            state.flag = StateFlag::ApplyK;
-            state.v = Some(Rc::new(Value::V0));
-        }
-        Term::D => {
-            state.flag = StateFlag::ApplyK;
-            state.v = Some(Rc::new(Value::D0));
+impl<P: Share> Render for State<P> {
+    fn render(&self, r: &mut Renderer) {
+        r.text(&format!("State: {:?}", self.flag));
+        r.newline();
+        if let Some(v) = &self.v {
+            r.text("Value: ");
+            render_value::<P>(r, v);
+            r.newline();
         }
-        Term::C => {
-            state.flag = StateFlag::ApplyK;
-            state.v = Some(Rc::new(Value::C0));
+        if let Some(t) = &self.t {
+            r.text("Term: [");
+            r.part(t.as_ref());
+            r.char(']');
+            r.newline();
         }
-        Term::R => {
-            state.flag = StateFlag::ApplyK;
-            state.v = Some(Rc::new(Value::Put0('\n')));
+        if let Some(w) = &self.w {
+            r.text("Walue: ");
+            render_value::<P>(r, w);
+            r.newline();
         }
-        Term::Put(c) => {
-            state.flag = StateFlag::ApplyK;
-            state.v = Some(Rc::new(Value::Put0(*c)));
+        r.text("Kont: ");
+        render_kont::<P>(r, &self.k);
+        r.newline();
+        r.text(&format!("Steps: {} ({} rules)", self.steps, self.rules));
+    }
+}
+
+impl<P: Share> Debug for State<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+// A shared machine can be moved to another thread between steps
+const _: () = {
+    fn assert_send<T: Send + 'static>() {}
+    let _ = assert_send::<State<Shared>>;
+};
+
+// The value of a primitive term, which needs no evaluation
+fn atom<P: Share>(t: &Term) -> Option<Value<P>> {
+    match t {
+        Term::I => Some(Value::I0),
+        Term::S => Some(Value::S0),
+        Term::K => Some(Value::K0),
+        Term::V => Some(Value::V0),
+        Term::D => Some(Value::D0),
+        Term::C => Some(Value::C0),
+        Term::R => Some(Value::Put0('\n')),
+        Term::Put(c) => Some(Value::Put0(*c)),
+        Term::App(_, _) => None,
+    }
+}
+
+fn eval<P: Share>(state: &mut State<P>) {
+    let t = take(&mut state.t).unwrap();
+    state.cover(|c| c.eval(&t));
+    match t.as_ref() {
+        // Fused: evaluating a primitive operator, popping its BindT and
+        // apply_t happen at once, so only the BindV frame is built
+        Term::App(t0, t1) if state.fuses() && !matches!(t0.as_ref(), Term::D | Term::App(..)) => {
+            state.cover(|c| c.eval(t0));
+            let v0 = alloc::<P>(atom(t0).unwrap(), &state.counter);
+            state.made(&v0, t0);
+            state.t = Some(t1.clone());
+            state.k = Some(P::new(Kont::BindV(v0, take(&mut state.k))));
+            state.rules += 3;
+            state.tally(|s| {
+                s.transitions[StateFlag::Eval as usize] += 1;
+                s.transitions[StateFlag::ApplyK as usize] += 1;
+                s.transitions[StateFlag::ApplyT as usize] += 1;
+                s.frames[stats::BIND_T] += 1;
+            });
+            state.pushed(stats::BIND_V);
         }
         Term::App(t0, t1) => {
             // State::Eval(t0, Some(Rc::new(Kont::BindT(t1, k.take()))))
-            state.t = Some(t0);
-            state.k = Some(Rc::new(Kont::BindT(t1, take(&mut state.k))));
+            state.t = Some(t0.clone());
+            state.k = Some(P::new(Kont::BindT(t1.clone(), take(&mut state.k))));
+            state.pushed(stats::BIND_T);
+        }
+        _ => {
+            let v = alloc::<P>(atom(&t).unwrap(), &state.counter);
+            state.made(&v, &t);
+            state.flag = StateFlag::ApplyK;
+            state.v = Some(v);
         }
     };
 }
 
-fn apply_t<'a>(state: &mut State<'a>) {
+fn apply_t<P: Share>(state: &mut State<P>) {
     let v = take(&mut state.v).unwrap();
     // safe because clause1 has a value for v and clause2 gives a value to v
     match v.as_ref() {
         Value::D0 => {
-            let t = state.t.unwrap();
+            let t = take(&mut state.t).unwrap();
+            state.cover(|c| c.delay(&t));
             state.flag = StateFlag::ApplyK;
-            state.v = Some(Rc::new(Value::D1T(t)));
+            state.v = Some(state.build(Value::D1T(t)));
         }
         _ => {
             state.flag = StateFlag::Eval;
-            state.k = Some(Rc::new(Kont::BindV(v, take(&mut state.k))));
+            state.k = Some(P::new(Kont::BindV(v, take(&mut state.k))));
+            state.pushed(stats::BIND_V);
         }
     }
 }
 
-fn apply_v<'a>(state: &mut State<'a>) {
+fn apply_v<P: Share>(state: &mut State<P>) {
     let v = take(&mut state.v).unwrap();
     let w = take(&mut state.w).unwrap();
+    state.tally(|s| s.applications[v.kind().index()] += 1);
     match v.as_ref() {
         Value::I0 => {
             state.flag = StateFlag::ApplyK;
             state.v = Some(w);
         }
         Value::Put0(c) => {
-            print!("{}", c);
-            std::io::stdout().flush().unwrap();
+            // what was printed before going back is not printed again
+            if state.output >= state.written {
+                output::put(*c);
+                state.written = state.output + c.len_utf8() as u64;
+            }
+            state.output += c.len_utf8() as u64;
+            state.tally(|s| s.output += 1);
+            state.printed(&v, *c);
             state.flag = StateFlag::ApplyK;
             state.v = Some(w);
         }
         Value::K0 => {
             state.flag = StateFlag::ApplyK;
-            state.v = Some(Rc::new(Value::K1(w)));
+            state.v = Some(state.build(Value::K1(w)));
         }
         Value::K1(w0) => {
             state.flag = StateFlag::ApplyK;
@@ -221,21 +662,33 @@ fn apply_v<'a>(state: &mut State<'a>) {
         }
         Value::C0 => {
             state.v = Some(w);
-            state.w = Some(Rc::new(Value::C1(state.k.clone())));
+            state.w = Some(state.build(Value::C1(state.k.clone(), state.depth)));
         }
-        Value::C1(k1) => {
+        Value::C1(k1, depth) => {
             state.flag = StateFlag::ApplyK;
             state.v = Some(w);
             state.k = k1.clone();
+            state.depth = *depth;
+            let depth = state.depth;
+            state.tally(|s| s.peak_depth = s.peak_depth.max(depth));
         }
         Value::D0 => {
             state.flag = StateFlag::ApplyK;
-            state.v = Some(Rc::new(Value::D1V(w)));
+            state.v = Some(state.build(Value::D1V(w)));
         }
         Value::D1T(t0) => {
+            // not while covering, which would miss the terms it skips
+            if state.simplify && (!P::STATS || state.coverage.is_none()) {
+                if let Some(v0) = pure_value(state, t0, 0) {
+                    state.v = Some(v0);
+                    state.w = Some(w);
+                    return;
+                }
+            }
             state.flag = StateFlag::Eval;
-            state.t = Some(t0);
-            state.k = Some(Rc::new(Kont::BindW(w, take(&mut state.k))));
+            state.t = Some(t0.clone());
+            state.k = Some(P::new(Kont::BindW(w, take(&mut state.k))));
+            state.pushed(stats::BIND_W);
         }
         Value::D1V(v0) => {
             state.v = Some(v0.clone());
@@ -243,74 +696,627 @@ fn apply_v<'a>(state: &mut State<'a>) {
         }
         Value::S0 => {
             state.flag = StateFlag::ApplyK;
-            state.v = Some(Rc::new(Value::S1(w)));
+            state.v = Some(state.build(Value::S1(w)));
         }
         Value::S1(v0) => {
             state.flag = StateFlag::ApplyK;
-            state.v = Some(Rc::new(Value::S2(v0.clone(), w)));
+            state.v = Some(state.build(Value::S2(v0.clone(), w)));
         }
         Value::S2(v0, v1) => {
+            if state.simplify && apply_simple_s2(state, v0, v1, &w) {
+                return;
+            }
             state.v = Some(v0.clone());
             state.w = Some(w.clone());
-            state.k = Some(Rc::new(Kont::SWait(v1.clone(), w, take(&mut state.k))));
+            state.k = Some(P::new(Kont::SWait(v1.clone(), w, take(&mut state.k))));
+            state.pushed(stats::S_WAIT);
         }
     };
 }
 
-fn apply_k<'a>(state: &mut State<'a>) {
-    let k = state.k.take();
-    match k {
-        Some(k) => match k.as_ref() {
-            Kont::BindT(t, k) => {
-                state.flag = StateFlag::ApplyT;
+// Values whose application always finishes in a single transition,
+// without printing, capturing a continuation or forcing a promise.
+fn is_pure<P: Share>(v: &Value<P>) -> bool {
+    matches!(
+        v,
+        Value::I0 | Value::K0 | Value::K1(_) | Value::S0 | Value::S1(_) | Value::V0 | Value::D0
+    )
+}
+
+// `v w` for a pure v, worked out ahead of time
+fn apply_pure<P: Share>(
+    state: &mut State<P>,
+    v: &P::Ptr<Value<P>>,
+    w: P::Ptr<Value<P>>,
+) -> Option<P::Ptr<Value<P>>> {
+    match v.as_ref() {
+        Value::I0 => Some(w),
+        Value::K0 => Some(state.build(Value::K1(w))),
+        Value::K1(w0) => Some(w0.clone()),
+        Value::S0 => Some(state.build(Value::S1(w))),
+        Value::S1(v0) => Some(state.build(Value::S2(v0.clone(), w))),
+        Value::V0 => Some(v.clone()),
+        Value::D0 => Some(state.build(Value::D1V(w))),
+        _ => None,
+    }
+}
+
+// Applies ``s v0 v1 to w at once for the effect-free shapes that behave
+// like a cheaper value, skipping the SWait and BindV frames:
+//   ``s`kx i    => x
+//   ``s`kx`ky   => `k`xy       (when x is pure)
+//   ``sk y      => i           (when y is pure)
+// The value itself is left as it was built, so it prints the same. Returns
+// whether it did.
+fn apply_simple_s2<P: Share>(
+    state: &mut State<P>,
+    v0: &P::Ptr<Value<P>>,
+    v1: &P::Ptr<Value<P>>,
+    w: &P::Ptr<Value<P>>,
+) -> bool {
+    match (v0.as_ref(), v1.as_ref()) {
+        (Value::K1(x), Value::I0) => {
+            state.v = Some(x.clone());
+            state.w = Some(w.clone());
+        }
+        (Value::K1(x), Value::K1(y)) if is_pure(x) => {
+            state.flag = StateFlag::ApplyK;
+            state.v = apply_pure(state, x, y.clone());
+        }
+        (Value::K0, y) if is_pure(y) => {
+            state.flag = StateFlag::ApplyK;
+            state.v = Some(w.clone());
+        }
+        _ => return false,
+    }
+    true
+}
+
+// The value of a term that only applies pure values, as in the `kv of
+// `d`kv, which cannot loop or print, so a promise of it may be forced at
+// once. None for any other term, or one nested too deep to be worth it.
+fn pure_value<P: Share>(
+    state: &mut State<P>,
+    t: &Arc<Term>,
+    depth: usize,
+) -> Option<P::Ptr<Value<P>>> {
+    let Term::App(t0, t1) = t.as_ref() else {
+        let v = alloc::<P>(atom(t).unwrap(), &state.counter);
+        state.made(&v, t);
+        return Some(v);
+    };
+    if depth >= 64 {
+        return None;
+    }
+    let v0 = pure_value(state, t0, depth + 1)?;
+    if let Value::D0 = v0.as_ref() {
+        return Some(state.build(Value::D1T(t1.clone())));
+    }
+    if !is_pure(&v0) {
+        return None;
+    }
+    let v1 = pure_value(state, t1, depth + 1)?;
+    apply_pure(state, &v0, v1)
+}
+
+fn apply_k<P: Share>(state: &mut State<P>) {
+    let Some(mut k) = state.k.take() else {
+        return;
+    };
+    // Fused: when no `c` holds on to the frame, it is overwritten with the
+    // BindV that would follow it instead of allocating a new one
+    if let Some(frame) = P::get_mut(&mut k) {
+        match frame {
+            Kont::BindT(..) if state.fuses() && !matches!(state.v.as_deref(), Some(Value::D0)) => {
+                let v = take(&mut state.v).unwrap();
+                // move the term out rather than cloning it, saving a
+                // round trip on its reference count
+                let Kont::BindT(t, k1) = replace(frame, Kont::BindV(v, None)) else {
+                    unreachable!()
+                };
+                if let Kont::BindV(_, next) = frame {
+                    *next = k1;
+                }
+                state.flag = StateFlag::Eval;
                 state.t = Some(t);
-                state.k = k.clone();
-            }
-            Kont::BindV(v, k) => {
-                let w = take(&mut state.v).unwrap();
-                state.flag = StateFlag::ApplyV;
-                state.v = Some(v.clone());
-                state.w = Some(w);
-                state.k = k.clone();
-            }
-            Kont::BindW(w1, k) => {
-                state.flag = StateFlag::ApplyV;
-                state.w = Some(w1.clone());
-                state.k = k.clone();
+                state.k = Some(k);
+                state.rules += 1;
+                state.tally(|s| {
+                    s.transitions[StateFlag::ApplyT as usize] += 1;
+                    s.frames[stats::BIND_V] += 1;
+                });
+                return;
             }
-            Kont::SWait(v1, v, k) => {
+            Kont::SWait(v1, v, k1) => {
                 let w = take(&mut state.v).unwrap();
                 state.flag = StateFlag::ApplyV;
                 state.v = Some(v1.clone());
                 state.w = Some(v.clone());
-                state.k = Some(Rc::new(Kont::BindV(w, k.clone())));
+                *frame = Kont::BindV(w, take(k1));
+                state.k = Some(k);
+                state.tally(|s| s.frames[stats::BIND_V] += 1);
+                return;
             }
-        },
-        None => ()
+            _ => (),
+        }
+    }
+    match k.as_ref() {
+        Kont::BindT(t, k) => {
+            state.flag = StateFlag::ApplyT;
+            state.t = Some(t.clone());
+            state.k = k.clone();
+            state.depth -= 1;
+        }
+        Kont::BindV(v, k) => {
+            let w = take(&mut state.v).unwrap();
+            state.flag = StateFlag::ApplyV;
+            state.v = Some(v.clone());
+            state.w = Some(w);
+            state.k = k.clone();
+            state.depth -= 1;
+        }
+        Kont::BindW(w1, k) => {
+            state.flag = StateFlag::ApplyV;
+            state.w = Some(w1.clone());
+            state.k = k.clone();
+            state.depth -= 1;
+        }
+        Kont::SWait(v1, v, k) => {
+            let w = take(&mut state.v).unwrap();
+            state.flag = StateFlag::ApplyV;
+            state.v = Some(v1.clone());
+            state.w = Some(v.clone());
+            state.k = Some(P::new(Kont::BindV(w, k.clone())));
+            state.tally(|s| s.frames[stats::BIND_V] += 1);
+            state.profile_frame();
+        }
     }
 }
 
-pub fn new<'a>(t: &'a Term) -> State<'a> {
+pub fn new<P: Share>(t: impl Into<Arc<Term>>) -> State<P> {
     State {
         flag: StateFlag::Eval,
-        t: Some(t),
+        t: Some(t.into()),
         v: None,
         w: None,
         k: None,
+        simplify: false,
+        fuse: true,
+        steps: 0,
+        rules: 0,
+        depth: 0,
+        counter: P::Counter::default(),
+        kept: 0,
+        output: 0,
+        written: 0,
+        limits: Limits::default(),
+        deadline: None,
+        stats: Stats::default(),
+        profile: None,
+        coverage: None,
+        provenance: None,
     }
 }
 
-impl<'a> State<'a> {
+impl<P: Share> State<P> {
+    /// Apply effect-free combinator shapes, and promises of terms that
+    /// only build values, in fewer steps. Values are built as they are, so
+    /// output, `c` and the final value are unaffected.
+    pub fn simplify(mut self, on: bool) -> Self {
+        self.simplify = on;
+        self
+    }
+
+    /// Take several rules in one transition where that is safe, as is the
+    /// default. Turned off, every transition applies a single rule, as the
+    /// machine in the playground does.
+    ///
+    /// # Panics
+    ///
+    /// If turned off on a machine that is not `Tallied`.
+    pub fn fusing(mut self, on: bool) -> Self {
+        assert!(P::STATS || on, "unfused transitions need a Tallied machine");
+        self.fuse = on;
+        self
+    }
+
+    /// Credit each step to the source term it works on.
+    ///
+    /// # Panics
+    ///
+    /// If the machine is not `Tallied`.
+    pub fn profiling(mut self, on: bool) -> Self {
+        assert!(P::STATS || !on, "profiling needs a Tallied machine");
+        self.profile = on.then(Box::default);
+        self
+    }
+
+    /// Record which terms are evaluated.
+    ///
+    /// # Panics
+    ///
+    /// If the machine is not `Tallied`.
+    pub fn covering(mut self, on: bool) -> Self {
+        assert!(P::STATS || !on, "coverage needs a Tallied machine");
+        self.coverage = on.then(Box::default);
+        self
+    }
+
+    /// Record where each printed character came from.
+    ///
+    /// # Panics
+    ///
+    /// If the machine is not `Tallied`.
+    pub fn recording_output(mut self, on: bool) -> Self {
+        assert!(P::STATS || !on, "output provenance needs a Tallied machine");
+        self.provenance = on.then(Box::default);
+        self
+    }
+
+    /// Caps what later runs may use. The wall-clock limit counts from
+    /// this call.
+    ///
+    /// # Panics
+    ///
+    /// If a value limit is given to a machine that is not `Counted`.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        assert!(
+            P::COUNTED || limits.values.is_none(),
+            "value limits need a Counted machine"
+        );
+        self.deadline = limits.time.map(|time| Instant::now() + time);
+        self.limits = limits;
+        self
+    }
+
     pub fn step(&mut self) {
+        self.steps += 1;
+        self.rules += 1;
+        let flag = self.flag;
+        self.tally(|s| s.transitions[flag as usize] += 1);
+        if P::STATS && self.profile.is_some() {
+            self.profiled_transition();
+            return;
+        }
+        self.transition();
+    }
+
+    fn profiled_transition(&mut self) {
+        let rules = self.rules - 1;
+        if let (Some(profile), Some(t)) = (&mut self.profile, &self.t) {
+            if self.flag == StateFlag::Eval {
+                profile.enter(t);
+            }
+        }
+        if let (Some(profile), Some(k)) = (&mut self.profile, &self.k) {
+            if self.flag == StateFlag::ApplyK {
+                profile.pop(frame_address::<P>(k));
+            }
+        }
+        if let (Some(profile), Some(v)) = (&mut self.profile, &self.v) {
+            if self.flag == StateFlag::ApplyV {
+                profile.apply(value_address::<P>(v));
+            }
+        }
+        self.transition();
+        let rules = self.rules - rules;
+        self.profile.as_mut().unwrap().credit(rules);
+    }
+
+    fn transition(&mut self) {
         match self.flag {
             StateFlag::Eval => eval(self),
             StateFlag::ApplyT => apply_t(self),
             StateFlag::ApplyV => apply_v(self),
-            StateFlag::ApplyK => apply_k(self)
+            StateFlag::ApplyK => apply_k(self),
+        }
+    }
+
+    // Bookkeeping for a frame of the given kind put on top of k
+    fn pushed(&mut self, frame: usize) {
+        self.depth += 1;
+        let depth = self.depth;
+        self.tally(|s| {
+            s.frames[frame] += 1;
+            s.peak_depth = s.peak_depth.max(depth);
+        });
+        self.profile_frame();
+    }
+
+    // Credits the frame on top of k to the term being worked on
+    fn profile_frame(&mut self) {
+        if !P::STATS {
+            return;
+        }
+        if let (Some(profile), Some(k)) = (&mut self.profile, &self.k) {
+            profile.push(frame_address::<P>(k));
+        }
+    }
+
+    // Whether rules may be fused; only ever false on a tallied machine, so
+    // the check costs nothing on the others
+    fn fuses(&self) -> bool {
+        !P::STATS || self.fuse
+    }
+
+    fn tally(&mut self, count: impl FnOnce(&mut Stats)) {
+        if P::STATS {
+            count(&mut self.stats);
+        }
+    }
+
+    // Remembers the primitive a value was evaluated from
+    fn made(&mut self, v: &P::Ptr<Value<P>>, t: &Arc<Term>) {
+        if !P::STATS {
+            return;
+        }
+        if let Some(profile) = &mut self.profile {
+            profile.made(value_address::<P>(v), t);
+        }
+        if !matches!(v.as_ref(), Value::Put0(_)) {
+            return;
+        }
+        if let Some(provenance) = &mut self.provenance {
+            provenance.made(value_address::<P>(v), t);
+        }
+    }
+
+    // A value built by the work of the current term
+    fn build(&mut self, v: Value<P>) -> P::Ptr<Value<P>> {
+        let v = alloc::<P>(v, &self.counter);
+        if P::STATS {
+            if let Some(profile) = &mut self.profile {
+                profile.built(value_address::<P>(&v));
+            }
+        }
+        v
+    }
+
+    fn printed(&mut self, v: &P::Ptr<Value<P>>, c: char) {
+        if !P::STATS {
+            return;
         }
+        if let Some(provenance) = &mut self.provenance {
+            provenance.print(value_address::<P>(v), c, self.steps, self.depth);
+        }
+    }
+
+    fn cover(&mut self, record: impl FnOnce(&mut Coverage)) {
+        if P::STATS {
+            if let Some(coverage) = &mut self.coverage {
+                record(coverage);
+            }
+        }
+    }
+
+    /// The first limit this state is over, or that its next step would go
+    /// over, if any. Checked before each step, so no step is taken and no
+    /// character printed past a limit.
+    pub fn check(&mut self) -> Option<Limit> {
+        let limits = &self.limits;
+        if limits.steps.is_some_and(|max| self.steps >= max) {
+            return Some(Limit::Steps(self.steps));
+        }
+        if let Some(max) = limits.values.filter(|&max| self.values() > max) {
+            // values dropped since the last count may be held by checkpoints
+            // alone, so count again before stopping
+            self.recount();
+            if self.values() > max {
+                return Some(Limit::Values(self.values()));
+            }
+        }
+        let limits = &self.limits;
+        if limits.depth.is_some_and(|max| self.depth > max) {
+            return Some(Limit::Depth(self.depth));
+        }
+        if let Some(max) = limits.output {
+            let printing = match (self.flag, self.v.as_deref()) {
+                (StateFlag::ApplyV, Some(Value::Put0(c))) => c.len_utf8() as u64,
+                _ => 0,
+            };
+            if self.output + printing > max {
+                return Some(Limit::Output(self.output));
+            }
+        }
+        // reading the clock is slow next to a step, so only look now and then
+        if let Some(deadline) = self.deadline {
+            if self.steps.is_multiple_of(1024) && Instant::now() > deadline {
+                return Some(Limit::Time(self.limits.time.unwrap()));
+            }
+        }
+        None
+    }
+
+    /// Number of frames in the current continuation.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Number of values allocated by this machine that are still alive,
+    /// not counting those only its checkpoints hold as of the last
+    /// `recount`. Always zero unless the machine is `Counted`.
+    pub fn values(&self) -> usize {
+        P::live(&self.counter) - self.kept
+    }
+
+    /// Counts again which of the live values only checkpoints hold. To be
+    /// called once checkpoints are dropped, as `values` would count too few.
+    pub fn recount(&mut self) {
+        self.kept = 0;
+        if P::COUNTED {
+            let held = self.held(&mut HashSet::new());
+            self.kept = P::live(&self.counter) - held.values;
+        }
+    }
+
+    /// The values and frames this state holds that are not in `seen`, which
+    /// they are added to. Walking several states with the same `seen`
+    /// counts what each holds beyond the ones before it.
+    pub fn held(&self, seen: &mut HashSet<usize>) -> Held {
+        let mut held = Held::default();
+        let mut todo: Vec<Part<P>> = Vec::new();
+        todo.extend(self.v.iter().chain(&self.w).map(Part::Value));
+        todo.extend(self.k.iter().map(Part::Kont));
+        while let Some(part) = todo.pop() {
+            if !seen.insert(part.address()) {
+                continue;
+            }
+            match part {
+                Part::Value(v) => {
+                    held.values += 1;
+                    match v.as_ref() {
+                        Value::S1(x) | Value::K1(x) | Value::D1V(x) => todo.push(Part::Value(x)),
+                        Value::S2(x, y) => todo.extend([Part::Value(x), Part::Value(y)]),
+                        Value::C1(Some(k), _) => todo.push(Part::Kont(k)),
+                        _ => {}
+                    }
+                }
+                Part::Kont(k) => {
+                    held.frames += 1;
+                    match k.as_ref() {
+                        Kont::BindV(v, _) | Kont::BindW(v, _) => todo.push(Part::Value(v)),
+                        Kont::SWait(v1, v, _) => todo.extend([Part::Value(v1), Part::Value(v)]),
+                        Kont::BindT(..) => {}
+                    }
+                    todo.extend(k.next().iter().map(Part::Kont));
+                }
+            }
+        }
+        held
+    }
+
+    /// Number of bytes the program has printed.
+    pub fn output(&self) -> u64 {
+        self.output
+    }
+
+    /// What the machine has done so far. Transitions are counted per rule,
+    /// as in the unfused machine. Always empty unless the machine is
+    /// `Tallied`.
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Where the steps went, if profiling.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_deref()
+    }
+
+    /// Which terms were evaluated, if recording coverage.
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_deref()
     }
 
-    pub fn extract(&self) -> Option<Rc<Value<'a>>> {
+    /// Where each printed character came from, if recording output.
+    pub fn provenance(&self) -> Option<&Provenance> {
+        self.provenance.as_deref()
+    }
+
+    /// A copy of the machine to come back to later, without its profile,
+    /// coverage or output provenance. Cheap, as it shares everything else,
+    /// down to the count of live values: what only checkpoints hold is left
+    /// out of `values` as `recount` finds it.
+    pub fn checkpoint(&self) -> Self {
+        State {
+            flag: self.flag,
+            t: self.t.clone(),
+            v: self.v.clone(),
+            w: self.w.clone(),
+            k: self.k.clone(),
+            simplify: self.simplify,
+            fuse: self.fuse,
+            steps: self.steps,
+            rules: self.rules,
+            depth: self.depth,
+            counter: self.counter.clone(),
+            kept: self.kept,
+            output: self.output,
+            written: self.written,
+            limits: self.limits,
+            deadline: self.deadline,
+            stats: self.stats.clone(),
+            profile: None,
+            coverage: None,
+            provenance: None,
+        }
+    }
+
+    /// Takes the machine back (or forward) to a checkpoint. Characters
+    /// already printed are not printed again as the machine gets back to
+    /// them, and the profile, coverage and output provenance keep what they
+    /// recorded, so steps taken again are counted again.
+    pub fn restore(&mut self, checkpoint: &Self) {
+        self.flag = checkpoint.flag;
+        self.t = checkpoint.t.clone();
+        self.v = checkpoint.v.clone();
+        self.w = checkpoint.w.clone();
+        self.k = checkpoint.k.clone();
+        self.steps = checkpoint.steps;
+        self.rules = checkpoint.rules;
+        self.depth = checkpoint.depth;
+        self.output = checkpoint.output;
+        self.written = self.written.max(checkpoint.written);
+        self.stats = checkpoint.stats.clone();
+        self.recount();
+    }
+
+    /// A one-line account of where the machine is, for error messages.
+    pub fn summary(&self) -> String {
+        let bounds = Bounds::SHORT.narrow(70);
+        let current = match (&self.flag, &self.t, &self.v) {
+            (StateFlag::Eval, Some(t), _) => format!("evaluating [{}]", bounds.render(t.as_ref())),
+            (_, _, Some(v)) => format!("holding {}", bounds.render(v.as_ref())),
+            _ => String::new(),
+        };
+        // only a counted machine knows how many values are live
+        let values = if P::COUNTED {
+            format!("{} live values, ", self.values())
+        } else {
+            String::new()
+        };
+        format!(
+            "after {} steps ({} rules), {:?}, {} frames deep, {}{} bytes printed, {}",
+            self.steps, self.rules, self.flag, self.depth, values, self.output, current,
+        )
+    }
+
+    /// Number of transitions taken so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Number of machine rules applied so far. A fused transition counts
+    /// once per rule it stands for, so this matches the step count of the
+    /// unfused machine.
+    pub fn rules(&self) -> u64 {
+        self.rules
+    }
+
+    pub fn flag(&self) -> StateFlag {
+        self.flag
+    }
+
+    /// The value held, or being applied.
+    pub fn v(&self) -> Option<&P::Ptr<Value<P>>> {
+        self.v.as_ref()
+    }
+
+    /// The argument a value is being applied to.
+    pub fn w(&self) -> Option<&P::Ptr<Value<P>>> {
+        self.w.as_ref()
+    }
+
+    /// The continuation.
+    pub fn k(&self) -> Option<&P::Ptr<Kont<P>>> {
+        self.k.as_ref()
+    }
+
+    /// The term being evaluated, if the machine is evaluating one.
+    pub fn term(&self) -> Option<&Arc<Term>> {
+        self.t.as_ref()
+    }
+
+    pub fn extract(&self) -> Option<P::Ptr<Value<P>>> {
         if self.flag == StateFlag::ApplyK && self.k.is_none() {
             self.v.clone()
         } else {
@@ -318,43 +1324,717 @@ impl<'a> State<'a> {
         }
     }
 
-    pub fn run(self) -> Rc<Value<'a>> {
-        let mut state = self;
+    /// Runs until the program finishes or `stop` holds for the state about
+    /// to be stepped.
+    pub fn run_until(
+        mut self,
+        mut stop: impl FnMut(&Self) -> bool,
+    ) -> Outcome<Self, P::Ptr<Value<P>>> {
+        loop {
+            if let Some(v) = self.extract() {
+                return Outcome::Finished(v);
+            }
+            if let Some(limit) = self.check() {
+                return Outcome::Exceeded(limit, self);
+            }
+            if stop(&self) {
+                return Outcome::Suspended(self);
+            }
+            self.step();
+        }
+    }
+
+    /// Runs for at most `steps` more transitions.
+    pub fn run_for(self, steps: u64) -> Outcome<Self, P::Ptr<Value<P>>> {
+        let end = self.steps + steps;
+        self.run_until(|s| s.steps >= end)
+    }
+
+    pub fn run(&mut self) -> Result<P::Ptr<Value<P>>, Limit> {
+        if self.limits.is_unlimited() {
+            loop {
+                self.step();
+                if let Some(v) = self.extract() {
+                    return Ok(v);
+                }
+            }
+        }
         loop {
-            state.step();
-            if let Some(v) = state.extract() {
-                return v;
+            if let Some(limit) = self.check() {
+                return Err(limit);
+            }
+            self.step();
+            if let Some(v) = self.extract() {
+                return Ok(v);
+            }
+        }
+    }
+
+    /// Like `run`, calling `sample` once every `every` steps.
+    pub fn run_sampling(
+        &mut self,
+        every: u64,
+        mut sample: impl FnMut(&Self),
+    ) -> Result<P::Ptr<Value<P>>, Limit> {
+        self.run_polling(every, |s| {
+            sample(s);
+            true
+        })
+        .unwrap()
+    }
+
+    /// Like `run`, calling `poll` once every `every` steps and stopping
+    /// with `None` when it gives false.
+    pub fn run_polling(
+        &mut self,
+        every: u64,
+        mut poll: impl FnMut(&Self) -> bool,
+    ) -> Option<Result<P::Ptr<Value<P>>, Limit>> {
+        let every = every.max(1);
+        let unlimited = self.limits.is_unlimited();
+        loop {
+            if !unlimited {
+                if let Some(limit) = self.check() {
+                    return Some(Err(limit));
+                }
+            }
+            self.step();
+            if let Some(v) = self.extract() {
+                return Some(Ok(v));
+            }
+            if self.steps.is_multiple_of(every) && !poll(self) {
+                return None;
+            }
+        }
+    }
+
+    /// The continuation as a call stack, outermost frame first, with what
+    /// the machine is doing on top. Frames are named after their kind and,
+    /// where the program knows one, the source position of their term.
+    pub fn stack(&self, program: &Program) -> Vec<String> {
+        let name = |kind: &str, t: &Term| match program.span(t) {
+            Some(span) => format!("{}[{}]", kind, span),
+            None => format!("{}[{}]", kind, truncate(&t.to_string(), 20)),
+        };
+        let mut stack = Vec::with_capacity(self.depth + 1);
+        stack.push(match (&self.flag, &self.t) {
+            (StateFlag::Eval, Some(t)) => name("Eval", t),
+            (flag, _) => format!("{:?}", flag),
+        });
+        let mut current = &self.k;
+        while let Some(k) = current {
+            stack.push(match k.as_ref() {
+                Kont::BindT(t, _) => name("BindT", t),
+                Kont::BindV(..) => "BindV".to_string(),
+                Kont::BindW(..) => "BindW".to_string(),
+                Kont::SWait(..) => "SWait".to_string(),
+            });
+            current = k.next();
+        }
+        stack.reverse();
+        stack
+    }
+
+    /// The state written down, sharing and all. Given the program it runs,
+    /// terms of the program are written as positions in it.
+    pub fn snapshot(&self, program: Option<&Program>) -> Snapshot {
+        let mut builder = Builder::new(program);
+        let mut id = |part| add_part::<P>(&mut builder, part);
+        let config = match self.flag {
+            StateFlag::Eval => {
+                let k = self.k.as_ref().map(|k| id(Part::Kont(k)));
+                snapshot::Config::Eval(builder.term(self.t.as_ref().unwrap()), k)
             }
+            StateFlag::ApplyT => {
+                let v = id(Part::Value(self.v.as_ref().unwrap()));
+                let k = self.k.as_ref().map(|k| id(Part::Kont(k)));
+                snapshot::Config::ApplyT(v, builder.term(self.t.as_ref().unwrap()), k)
+            }
+            StateFlag::ApplyV => {
+                let v = id(Part::Value(self.v.as_ref().unwrap()));
+                let w = id(Part::Value(self.w.as_ref().unwrap()));
+                let k = self.k.as_ref().map(|k| id(Part::Kont(k)));
+                snapshot::Config::ApplyV(v, w, k)
+            }
+            StateFlag::ApplyK => {
+                let v = id(Part::Value(self.v.as_ref().unwrap()));
+                let k = self.k.as_ref().map(|k| id(Part::Kont(k)));
+                snapshot::Config::ApplyK(k, v)
+            }
+        };
+        builder.finish(config, self.rules)
+    }
+}
+
+/// Values and frames, each counted once however many hold it.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Held {
+    pub values: usize,
+    pub frames: usize,
+}
+
+// A value or frame being written to a snapshot, or counted
+enum Part<'a, P: Share> {
+    Value(&'a P::Ptr<Value<P>>),
+    Kont(&'a P::Ptr<Kont<P>>),
+}
+
+impl<P: Share> Clone for Part<'_, P> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<P: Share> Copy for Part<'_, P> {}
+
+impl<P: Share> Part<'_, P> {
+    fn address(self) -> usize {
+        match self {
+            Part::Value(v) => value_address::<P>(v),
+            Part::Kont(k) => frame_address::<P>(k),
         }
     }
-    // todo: add a repl
 }
 
-pub fn main(term: Term, interactive: bool) {
-    let state = new(&term);
+// Adds a value or frame and all it holds to a snapshot, parts first. Done
+// without recursion, as continuations get very deep.
+fn add_part<P: Share>(builder: &mut Builder, part: Part<P>) -> snapshot::Id {
+    let mut todo = vec![(part, false)];
+    while let Some((part, ready)) = todo.pop() {
+        if builder.get(part.address()).is_some() {
+            continue;
+        }
+        if !ready {
+            todo.push((part, true));
+            match part {
+                Part::Value(v) => match v.as_ref() {
+                    Value::S1(x) | Value::K1(x) | Value::D1V(x) => {
+                        todo.push((Part::Value(x), false))
+                    }
+                    Value::S2(x, y) => {
+                        todo.push((Part::Value(y), false));
+                        todo.push((Part::Value(x), false));
+                    }
+                    Value::C1(Some(k), _) => todo.push((Part::Kont(k), false)),
+                    _ => {}
+                },
+                Part::Kont(k) => {
+                    if let Some(next) = k.next() {
+                        todo.push((Part::Kont(next), false));
+                    }
+                    match k.as_ref() {
+                        Kont::BindV(v, _) | Kont::BindW(v, _) => todo.push((Part::Value(v), false)),
+                        Kont::SWait(v1, v, _) => {
+                            todo.push((Part::Value(v), false));
+                            todo.push((Part::Value(v1), false));
+                        }
+                        Kont::BindT(..) => {}
+                    }
+                }
+            }
+            continue;
+        }
+        let value = |v: &P::Ptr<Value<P>>| builder.get(value_address::<P>(v)).unwrap();
+        let frame = |k: &Option<P::Ptr<Kont<P>>>| {
+            k.as_ref()
+                .map(|k| builder.get(frame_address::<P>(k)).unwrap())
+        };
+        let node = match part {
+            Part::Value(v) => Node::Value(match v.as_ref() {
+                Value::I0 => snapshot::Value::I0,
+                Value::S0 => snapshot::Value::S0,
+                Value::K0 => snapshot::Value::K0,
+                Value::V0 => snapshot::Value::V0,
+                Value::D0 => snapshot::Value::D0,
+                Value::C0 => snapshot::Value::C0,
+                Value::Put0(c) => snapshot::Value::Put0(*c),
+                Value::S1(x) => snapshot::Value::S1(value(x)),
+                Value::S2(x, y) => snapshot::Value::S2(value(x), value(y)),
+                Value::K1(x) => snapshot::Value::K1(value(x)),
+                Value::D1V(x) => snapshot::Value::D1V(value(x)),
+                Value::C1(k, _) => snapshot::Value::C1(frame(k)),
+                Value::D1T(t) => snapshot::Value::D1T(builder.term(t)),
+            }),
+            Part::Kont(k) => Node::Kont(match k.as_ref() {
+                Kont::BindV(v, next) => snapshot::Kont::BindV(value(v), frame(next)),
+                Kont::BindW(w, next) => snapshot::Kont::BindW(value(w), frame(next)),
+                Kont::SWait(v1, v, next) => snapshot::Kont::SWait(value(v1), value(v), frame(next)),
+                Kont::BindT(t, next) => {
+                    let next = frame(next);
+                    snapshot::Kont::BindT(builder.term(t), next)
+                }
+            }),
+        };
+        builder.add(part.address(), node);
+    }
+    builder.get(part.address()).unwrap()
+}
 
-    if interactive {
-        let mut state = state;
-        println!("{}", state);
-        println!("Press enter to step, or Ctrl-C to exit. `r` to run to completion.");
-        let result = loop {
-            print!("> ");
-            std::io::stdout().flush().unwrap();
-            let mut input = String::new();
-            std::io::stdin().read_line(&mut input).unwrap();
-            match input.trim() {
-                "r" => break state.run(),
-                _ => (),
+/// A machine in the state a snapshot was taken in. Terms written as
+/// positions are taken from `program`, which should be the one the snapshot
+/// came from. Steps are counted from the rules the snapshot had applied.
+pub fn from_snapshot<P: Share>(
+    snapshot: &Snapshot,
+    program: Option<&Program>,
+) -> Result<State<P>, snapshot::Error> {
+    let terms = snapshot.terms(program)?;
+    let mut state = new::<P>(Term::I);
+    let mut values: Vec<Option<P::Ptr<Value<P>>>> = Vec::with_capacity(snapshot.nodes.len());
+    let mut konts: Vec<Option<P::Ptr<Kont<P>>>> = Vec::with_capacity(snapshot.nodes.len());
+    for node in &snapshot.nodes {
+        let value = |id: snapshot::Id| values[id].clone().unwrap();
+        let frame = |id: Option<snapshot::Id>| id.map(|id| konts[id].clone().unwrap());
+        let term = |id: snapshot::Id| terms[id].clone().unwrap();
+        let (v, k) = match *node {
+            Node::Value(v) => {
+                let v = match v {
+                    snapshot::Value::I0 => Value::I0,
+                    snapshot::Value::S0 => Value::S0,
+                    snapshot::Value::K0 => Value::K0,
+                    snapshot::Value::V0 => Value::V0,
+                    snapshot::Value::D0 => Value::D0,
+                    snapshot::Value::C0 => Value::C0,
+                    snapshot::Value::Put0(c) => Value::Put0(c),
+                    snapshot::Value::S1(x) => Value::S1(value(x)),
+                    snapshot::Value::S2(x, y) => Value::S2(value(x), value(y)),
+                    snapshot::Value::K1(x) => Value::K1(value(x)),
+                    snapshot::Value::D1T(t) => Value::D1T(term(t)),
+                    snapshot::Value::D1V(x) => Value::D1V(value(x)),
+                    snapshot::Value::C1(k) => {
+                        let k = frame(k);
+                        let depth = depth::<P>(&k);
+                        Value::C1(k, depth)
+                    }
+                };
+                (Some(alloc::<P>(v, &state.counter)), None)
             }
-            state.step();
-            if let Some(v) = state.extract() {
-                break v;
+            Node::Kont(k) => {
+                let k = match k {
+                    snapshot::Kont::BindT(t, next) => Kont::BindT(term(t), frame(next)),
+                    snapshot::Kont::BindV(v, next) => Kont::BindV(value(v), frame(next)),
+                    snapshot::Kont::BindW(w, next) => Kont::BindW(value(w), frame(next)),
+                    snapshot::Kont::SWait(v1, v, next) => {
+                        Kont::SWait(value(v1), value(v), frame(next))
+                    }
+                };
+                (None, Some(P::new(k)))
             }
-            println!("{}", state);
+            _ => (None, None),
         };
-        println!("-----\nResult:\n{}", result);
+        values.push(v);
+        konts.push(k);
+    }
+
+    let value = |id: snapshot::Id| values[id].clone();
+    let frame = |id: Option<snapshot::Id>| id.and_then(|id| konts[id].clone());
+    let term = |id: snapshot::Id| terms[id].clone();
+    (state.flag, state.t, state.v, state.w, state.k) = match snapshot.config {
+        snapshot::Config::Eval(t, k) => (StateFlag::Eval, term(t), None, None, frame(k)),
+        snapshot::Config::ApplyT(v, t, k) => (StateFlag::ApplyT, term(t), value(v), None, frame(k)),
+        snapshot::Config::ApplyV(v, w, k) => {
+            (StateFlag::ApplyV, None, value(v), value(w), frame(k))
+        }
+        snapshot::Config::ApplyK(k, v) => (StateFlag::ApplyK, None, value(v), None, frame(k)),
+    };
+    state.depth = depth::<P>(&state.k);
+    state.steps = snapshot.steps;
+    state.rules = snapshot.steps;
+    Ok(state)
+}
+
+/// Runs a program, or takes up a run saved in a snapshot of it.
+pub fn main(
+    program: &Program,
+    snapshot: Option<&Snapshot>,
+    options: &Options,
+) -> Result<(), Limit> {
+    // tracing and the debugger run unfused, which takes a tallied machine
+    let tallied = options.stats.is_some()
+        || options.profile.is_some()
+        || options.coverage.is_some()
+        || options.provenance.is_some()
+        || options.trace.is_some()
+        || options.interactive;
+    match (options.limits.values.is_some(), tallied) {
+        (true, true) => run_main::<Counted<Tallied<Local>>>(program, snapshot, options),
+        (true, false) => run_main::<Counted<Local>>(program, snapshot, options),
+        (false, true) => run_main::<Tallied<Local>>(program, snapshot, options),
+        (false, false) => run_main::<Local>(program, snapshot, options),
+    }
+}
+
+fn run_main<P: Share>(
+    program: &Program,
+    snapshot: Option<&Snapshot>,
+    options: &Options,
+) -> Result<(), Limit> {
+    let interactive = options.interactive;
+    let state: State<P> = match snapshot {
+        // the program came from the snapshot, which it was checked against
+        Some(snapshot) => from_snapshot(snapshot, Some(program)).expect("snapshot was checked"),
+        None => new(program.term.clone()),
+    };
+    let mut state = state
+        .simplify(options.simplify)
+        .limits(options.limits)
+        .profiling(options.profile.is_some())
+        .covering(options.coverage.is_some())
+        .recording_output(options.provenance.is_some())
+        // a primitive applied in one fused transition never stops in Eval,
+        // where a breakpoint on its source would catch it
+        .fusing(options.trace.is_none() && !interactive);
+    let mut flamegraph = options.flamegraph.as_ref().map(|_| Flamegraph::default());
+    let mut trace = options
+        .trace
+        .as_ref()
+        .and_then(|path| match File::create(path) {
+            Ok(file) => Some(Trace::new(BufWriter::new(file))),
+            Err(e) => {
+                eprintln!("Could not write trace to {}: {}", path.display(), e);
+                None
+            }
+        });
+    if let Some(trace) = &mut trace {
+        trace.record(&state, program);
+    }
+    let interrupts = Interrupts::install()
+        .inspect_err(|e| eprintln!("Could not handle Ctrl-C: {}", e))
+        .ok();
+    // runs until the program finishes or a signal comes in
+    let run = |state: &mut State<P>,
+               flamegraph: &mut Option<Flamegraph>,
+               trace: &mut Option<Trace<BufWriter<File>>>| {
+        match (flamegraph, trace, &interrupts) {
+            (None, None, Some(interrupts)) => {
+                state.run_polling(interrupt::POLL, |_| !interrupts.pending())
+            }
+            (None, None, None) => Some(state.run()),
+            (flamegraph, trace, _) => {
+                let every = if trace.is_some() {
+                    1
+                } else {
+                    options.sample_every
+                };
+                state.run_polling(every, |s| {
+                    if let Some(trace) = trace.as_mut() {
+                        trace.record(s, program);
+                    }
+                    if let Some(flamegraph) = flamegraph.as_mut() {
+                        if s.steps.is_multiple_of(options.sample_every.max(1)) {
+                            flamegraph.add(s.stack(program));
+                        }
+                    }
+                    !interrupts.as_ref().is_some_and(Interrupts::pending)
+                })
+            }
+        }
+    };
+
+    let result = if interactive {
+        match Debugger::new(program, options.history)
+            .bounds(options.bounds)
+            .interrupts(interrupts.as_ref())
+            .run(&mut state)
+        {
+            Some(result) => result,
+            None => return Ok(()),
+        }
     } else {
-        let result = state.run();
-        println!("Result:\n{}", result);
+        loop {
+            if let Some(result) = run(&mut state, &mut flamegraph, &mut trace) {
+                break result;
+            }
+            let interrupts = interrupts
+                .as_ref()
+                .expect("only a signal stops a run early");
+            if interrupts.take_report() {
+                eprintln!("\n{}", state.summary());
+                if !interrupts.pending() {
+                    continue;
+                }
+            }
+            eprintln!("\nInterrupted {}", state.summary());
+            // the run may end here
+            write_trace(&mut trace, options);
+            match interrupts.ask(options.snapshot.is_some()) {
+                Choice::Resume => {}
+                Choice::Inspect => {
+                    output::set(Flush::None);
+                    match Debugger::new(program, options.history)
+                        .bounds(options.bounds)
+                        .interrupts(Some(interrupts))
+                        .run(&mut state)
+                    {
+                        Some(result) => break result,
+                        None => return Ok(()),
+                    }
+                }
+                Choice::Save => {
+                    save(
+                        &state.snapshot(Some(program)),
+                        options.snapshot.as_ref().unwrap(),
+                    );
+                    std::process::exit(130)
+                }
+                Choice::Quit => std::process::exit(130),
+            }
+        }
+    };
+    output::flush();
+    match &result {
+        Ok(result) if interactive => {
+            println!("-----");
+            print_result(result.as_ref(), options, Value::readback);
+            println!("Steps: {} ({} rules)", state.steps(), state.rules());
+        }
+        Ok(result) => print_result(result.as_ref(), options, Value::readback),
+        Err(limit) => {
+            eprintln!("\nStopped: {}\n{}", limit, state.summary());
+            if let Some(path) = &options.snapshot {
+                save(&state.snapshot(Some(program)), path);
+            }
+        }
+    }
+    if let Some(format) = options.stats {
+        eprintln!("{}", state.stats().format(format));
+    }
+    if let (Some(path), Some(profile)) = (&options.profile, state.profile()) {
+        let report = profile.report(program, 20);
+        match path {
+            Some(path) => {
+                if let Err(e) = std::fs::write(path, report) {
+                    eprintln!("Could not write profile to {}: {}", path.display(), e);
+                }
+            }
+            None => eprint!("{}", report),
+        }
+    }
+    if let (Some(path), Some(coverage)) = (&options.coverage, state.coverage()) {
+        let report = match path.extension() {
+            Some(ext) if ext == "html" || ext == "htm" => coverage.html(program),
+            _ => coverage.text(program),
+        };
+        if let Err(e) = std::fs::write(path, report) {
+            eprintln!("Could not write coverage to {}: {}", path.display(), e);
+        }
+    }
+    if let (Some(path), Some(provenance)) = (&options.provenance, state.provenance()) {
+        if let Err(e) = std::fs::write(path, provenance.report(program)) {
+            eprintln!("Could not write provenance to {}: {}", path.display(), e);
+        }
+    }
+    write_trace(&mut trace, options);
+    if let Some(path) = &options.dot {
+        write_graph(&state, program, path);
+    }
+    if let (Some(path), Some(flamegraph)) = (&options.flamegraph, flamegraph) {
+        if let Err(e) = std::fs::write(path, flamegraph.folded()) {
+            eprintln!("Could not write flamegraph to {}: {}", path.display(), e);
+        }
+    }
+    result.map(|_| ())
+}
+
+fn write_trace(trace: &mut Option<Trace<BufWriter<File>>>, options: &Options) {
+    if let (Some(path), Some(trace)) = (&options.trace, trace) {
+        if let Err(e) = trace.flush() {
+            eprintln!("Could not write trace to {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Writes the values and frames a state holds as a Graphviz graph.
+pub fn write_graph<P: Share>(state: &State<P>, program: &Program, path: &Path) {
+    let written = dot::graph(&state.snapshot(Some(program)))
+        .map_err(|e| e.to_string())
+        .and_then(|graph| std::fs::write(path, graph).map_err(|e| e.to_string()));
+    if let Err(e) = written {
+        eprintln!("Could not write graph to {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machines::anaive;
+    use crate::term::{parse_program, parse_term};
+
+    fn run(source: &str) -> Rc<Value<Local>> {
+        new::<Local>(Arc::new(parse_term(source).unwrap()))
+            .run()
+            .unwrap()
+    }
+
+    #[test]
+    fn readback_round_trips_on_both_machines() {
+        // S1, S2, K1, D1T inside K1, D1V, and an S2 whose parts are one
+        // value. A D1V comes back as a D1T, so it is the terms that match
+        for source in ["`s`ki", "``s`kk`ki", "`k`d`ii", "```s`kdi`ki", "```ssi`ki"] {
+            let term = run(source).readback().unwrap().to_string();
+            let again = run(&term);
+            assert_eq!(again.readback().unwrap().to_string(), term, "{}", source);
+            let copied = anaive::new(parse_term(&term).unwrap()).run();
+            assert_eq!(copied.to_string(), again.to_string(), "{}", source);
+            assert_eq!(copied.readback().unwrap().to_string(), term, "{}", source);
+        }
+    }
+
+    #[test]
+    fn continuations_have_no_readback() {
+        assert!(run("`ci").readback().is_none());
+        let copied = anaive::new(parse_term("`ci").unwrap()).run();
+        assert!(copied.readback().is_none());
+    }
+
+    #[test]
+    fn fused_rules_add_up_to_the_unfused_steps() {
+        // continuations taken and thrown, promises made and forced, a
+        // numeral applied 27 times, and one that prints, stopped before it
+        // does so the test prints nothing
+        let three = "``s``s`ksk``s``s`kski";
+        let sources = [
+            "``cc`c`d`ki".to_string(),
+            "```s``s`kk`kc`kd`ki".to_string(),
+            "```s`kd`k`kii".to_string(),
+            format!("```{three}{three}`kki"),
+            format!("``{three}`d.xi"),
+        ];
+        let limits = Limits {
+            output: Some(0),
+            ..Limits::default()
+        };
+        for source in &sources {
+            let term = Arc::new(parse_term(source).unwrap());
+            let mut fused = new::<Local>(term.clone()).limits(limits);
+            let mut unfused = new::<Tallied<Local>>(term).limits(limits).fusing(false);
+            let (v, w) = (fused.run(), unfused.run());
+            assert_eq!(format!("{:?}", v), format!("{:?}", w), "{}", source);
+            assert_eq!(fused.rules(), unfused.steps(), "{}", source);
+            assert!(fused.steps() < unfused.steps(), "{}", source);
+            assert_eq!(fused.output(), unfused.output(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn run_for_suspends_and_resumes() {
+        let state = new::<Local>(Arc::new(parse_term("```sii``sii").unwrap()));
+        let Outcome::Suspended(state) = state.run_for(100) else {
+            panic!("the loop should still be running");
+        };
+        assert_eq!(state.steps(), 100);
+        let Outcome::Suspended(state) = state.run_for(100) else {
+            panic!("the loop should still be running");
+        };
+        assert_eq!(state.steps(), 200);
+
+        let state = new::<Local>(Arc::new(parse_term("```s`kkii").unwrap()));
+        let Outcome::Suspended(state) = state.run_for(1) else {
+            panic!("the program should not be done in one step");
+        };
+        let Outcome::Finished(v) = state.run_for(100) else {
+            panic!("the program should be done");
+        };
+        assert_eq!(v.to_string(), "`ki");
+    }
+
+    #[test]
+    fn limits_stop_the_run_at_their_bound() {
+        let run = |source: &str, limits: Limits| {
+            let term = Arc::new(parse_term(source).unwrap());
+            let mut state = new::<Counted<Local>>(term).limits(limits);
+            let Err(limit) = state.run() else {
+                panic!("{} should go over a limit", source);
+            };
+            (limit, state)
+        };
+        let forever = "```sii``sii";
+
+        let (limit, state) = run(
+            forever,
+            Limits {
+                steps: Some(50),
+                ..Limits::default()
+            },
+        );
+        assert_eq!(limit, Limit::Steps(50));
+        assert_eq!(state.steps(), 50);
+
+        let deep = format!("{}i", "`i".repeat(50));
+        let (limit, state) = run(
+            &deep,
+            Limits {
+                depth: Some(10),
+                ..Limits::default()
+            },
+        );
+        assert_eq!(limit, Limit::Depth(11));
+        assert_eq!(state.depth(), 11);
+
+        // each `k waits on its argument in a frame of its own
+        let growing = format!("{}i", "`k".repeat(50));
+        let (limit, state) = run(
+            &growing,
+            Limits {
+                values: Some(20),
+                ..Limits::default()
+            },
+        );
+        assert_eq!(limit, Limit::Values(21));
+        assert_eq!(state.values(), 21);
+
+        // stopped before a character that would go over, even part of one;
+        // none gets as far as printing, so the test prints nothing
+        for (source, max) in [("`.xi", 0), ("`.\u{e9}i", 1)] {
+            let (limit, state) = run(
+                source,
+                Limits {
+                    output: Some(max),
+                    ..Limits::default()
+                },
+            );
+            assert_eq!(limit, Limit::Output(0), "{}", source);
+            assert_eq!(state.output(), 0, "{}", source);
+        }
+    }
+
+    #[test]
+    fn simplifying_takes_fewer_steps_to_the_same_value() {
+        // each shape, one inside another, a promise of a pure term, and a
+        // continuation captured under a shortcut
+        for source in [
+            "```s`kvii",
+            "```s`kk`kii",
+            "```sk`kii",
+            "````s`kk`k`kvii",
+            "``d`kvi",
+            "```s`kcii",
+        ] {
+            let term = Arc::new(parse_term(source).unwrap());
+            let mut plain = new::<Local>(term.clone());
+            let mut simple = new::<Local>(term).simplify(true);
+            let (v, w) = (plain.run().unwrap(), simple.run().unwrap());
+            assert_eq!(v.to_string(), w.to_string(), "{}", source);
+            assert!(simple.steps < plain.steps, "{}", source);
+        }
+    }
+
+    #[test]
+    fn profile_credits_applications_to_where_values_were_made() {
+        // the numeral 27 on line 2 applies the function on line 3 to i, which
+        // applies i 27 times; the root on line 1 only starts them off
+        let three = "``s``s`ksk``s``s`kski";
+        let source = format!("``\n`{three}{three}\n``{three}{three}i\ni");
+        let program = parse_program(&source).unwrap();
+        let mut state = new::<Tallied<Local>>(program.term.clone()).profiling(true);
+        state.run().unwrap();
+        let profile = state.profile().unwrap();
+        let mut lines = [0; 4];
+        for (t, n) in profile.hottest() {
+            lines[program.span(t).unwrap().line - 1] += n;
+        }
+        assert_eq!(lines.iter().sum::<u64>(), profile.total());
+        assert!(lines[0] * 100 < profile.total(), "{:?}", lines);
+        assert!(lines[1] * 20 > profile.total(), "{:?}", lines);
+        assert!(lines[2] * 2 > profile.total(), "{:?}", lines);
     }
 }
@@ -0,0 +1,288 @@
+use super::arc::{self, Local, Value};
+use super::render::Bounds;
+use super::{Limit, Limits};
+use crate::term::Term;
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::rc::Rc;
+use std::sync::Arc;
+
+// Data as terms, and back
+//
+// Numbers are Church numerals, `f` applied n times to `x`, and booleans
+// pick the first (`k`) or the second (`` `ki ``) of two arguments. Pairs
+// hand both their parts to a function, and lists are Scott lists: given
+// what to do with an empty list and what to do with a head and a tail,
+// they do one or the other. Bytes are numbers, so byte strings are lists.
+//
+// A value is read back from a term by applying it to markers on the
+// sharing machine and looking at what comes out. The markers print
+// characters the term never prints, so it cannot make them itself: a
+// numeral applied to `k` and a marker gives the marker under as many `k`s
+// as it stands for, a boolean applied to two markers one or the other, and
+// a list applied to a marker and `` `k`k `` with another either the first
+// or, having handed over a head and a tail, the second. Data prints
+// nothing, so a value that would print as it is probed, or apply a marker,
+// is stopped before it does and refused.
+
+/// Most steps a probe may take before the value is taken not to be data.
+const PROBE: u64 = 100_000_000;
+
+/// The Church numeral for n, built by doubling so it stays shallow.
+pub fn nat(n: u64) -> Arc<Term> {
+    match n {
+        0 => app(Term::K.into(), Term::I.into()),
+        1 => Term::I.into(),
+        2 => app(succ(), nat(1)),
+        n if n % 2 == 0 => app(app(mul(), nat(2)), nat(n / 2)),
+        n => app(succ(), nat(n - 1)),
+    }
+}
+
+pub fn bool(b: bool) -> Arc<Term> {
+    if b {
+        Term::K.into()
+    } else {
+        app(Term::K.into(), Term::I.into())
+    }
+}
+
+/// A pair: applied to a function, it applies it to a, then the result to b.
+pub fn pair(a: Arc<Term>, b: Arc<Term>) -> Arc<Term> {
+    // ``s``si`ka`kb
+    let first = app(app(Term::S.into(), Term::I.into()), app(Term::K.into(), a));
+    app(app(Term::S.into(), first), app(Term::K.into(), b))
+}
+
+pub fn list(items: impl IntoIterator<Item = Arc<Term>>) -> Arc<Term> {
+    let items: Vec<Arc<Term>> = items.into_iter().collect();
+    // nil = k, and cons h t = `k(pair h t)
+    items
+        .into_iter()
+        .rev()
+        .fold(Term::K.into(), |t, h| app(Term::K.into(), pair(h, t)))
+}
+
+pub fn bytes(s: &[u8]) -> Arc<Term> {
+    list(s.iter().map(|&b| nat(b as u64)))
+}
+
+fn app(t0: Arc<Term>, t1: Arc<Term>) -> Arc<Term> {
+    Arc::new(Term::App(t0, t1))
+}
+
+// `s``s`ksk
+fn succ() -> Arc<Term> {
+    app(Term::S.into(), mul())
+}
+
+// ``s`ksk
+fn mul() -> Arc<Term> {
+    let ks = app(Term::K.into(), Term::S.into());
+    app(app(Term::S.into(), ks), Term::K.into())
+}
+
+/// Why a value could not be read as data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error(pub String);
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// What kind of data a result is read as.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Nat,
+    Bool,
+    /// A list of numbers
+    List,
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Kind::Nat => write!(f, "a number"),
+            Kind::Bool => write!(f, "a boolean"),
+            Kind::List => write!(f, "a list"),
+        }
+    }
+}
+
+impl Kind {
+    /// The data a term evaluates to, written the Rust way.
+    pub fn decode(self, t: Arc<Term>) -> Result<String, Error> {
+        match self {
+            Kind::Nat => to_nat(t).map(|n| n.to_string()),
+            Kind::Bool => to_bool(t).map(|b| b.to_string()),
+            Kind::List => to_list(t).map(|items| format!("{:?}", items)),
+        }
+    }
+}
+
+// Characters t does not print, as many as asked for. Printing them stands
+// for what t is given as it is probed, which t cannot make itself.
+fn markers(t: &Arc<Term>, n: usize) -> Vec<char> {
+    let mut used = HashSet::new();
+    let mut seen = HashSet::new();
+    let mut todo = vec![t];
+    while let Some(t) = todo.pop() {
+        if !seen.insert(Arc::as_ptr(t)) {
+            continue;
+        }
+        match t.as_ref() {
+            Term::Put(c) => {
+                used.insert(*c);
+            }
+            Term::App(t0, t1) => {
+                todo.push(t0);
+                todo.push(t1);
+            }
+            _ => {}
+        }
+    }
+    ('\u{E000}'..='\u{F8FF}')
+        .filter(|c| !used.contains(c))
+        .take(n)
+        .collect()
+}
+
+// The value of t applied to each of the arguments, refused if it prints
+// or applies a marker
+fn probe(t: Arc<Term>, args: &[Term], markers: &[char]) -> Result<Rc<Value<Local>>, Error> {
+    let t = args.iter().fold(t, |t, m| app(t, m.clone().into()));
+    let limits = Limits {
+        steps: Some(PROBE),
+        output: Some(0),
+        ..Limits::default()
+    };
+    let mut state = arc::new::<Local>(t).limits(limits);
+    state
+        .run()
+        .map_err(|limit| match (limit, state.v().map(|v| v.as_ref())) {
+            (Limit::Output(_), Some(Value::Put0(c))) if markers.contains(c) => {
+                Error("it applies what it is probed with".into())
+            }
+            (Limit::Output(_), _) => Error("it prints as it is probed".into()),
+            (limit, _) => Error(format!("probing it: {}", limit)),
+        })
+}
+
+fn unexpected(what: &str, v: &Value<Local>) -> Error {
+    Error(format!(
+        "expected {}, got {}",
+        what,
+        Bounds::SHORT.narrow(60).render(v)
+    ))
+}
+
+/// The number a Church numeral stands for.
+pub fn to_nat(t: Arc<Term>) -> Result<u64, Error> {
+    let [zero] = markers(&t, 1)[..] else {
+        unreachable!()
+    };
+    let mut v = probe(t, &[Term::K, Term::Put(zero)], &[zero])?;
+    let mut n = 0;
+    loop {
+        let next = match v.as_ref() {
+            Value::Put0(c) if *c == zero => return Ok(n),
+            Value::K1(w) => w.clone(),
+            w => return Err(unexpected("the marker under `k`s", w)),
+        };
+        // one link at a time: dropping the whole chain at once would
+        // recurse as deep as it is long
+        v = next;
+        n += 1;
+    }
+}
+
+pub fn to_bool(t: Arc<Term>) -> Result<bool, Error> {
+    let [yes, no] = markers(&t, 2)[..] else {
+        unreachable!()
+    };
+    match probe(t, &[Term::Put(yes), Term::Put(no)], &[yes, no])?.as_ref() {
+        Value::Put0(c) if *c == yes => Ok(true),
+        Value::Put0(c) if *c == no => Ok(false),
+        v => Err(unexpected("one of the markers", v)),
+    }
+}
+
+/// The numbers in a list of numerals.
+pub fn to_list(t: Arc<Term>) -> Result<Vec<u64>, Error> {
+    let mut items = Vec::new();
+    let mut t = t;
+    loop {
+        let [nil, cons] = markers(&t, 2)[..] else {
+            unreachable!()
+        };
+        // first whether it is empty or a cell, by markers it cannot make:
+        // a cell hands its head and tail to ``k`k<cons>, which gives <cons>
+        let cell = Term::App(Term::K.into(), app(Term::K.into(), Term::Put(cons).into()));
+        let args = [Term::Put(nil), cell];
+        match probe(t.clone(), &args, &[nil, cons])?.as_ref() {
+            Value::Put0(c) if *c == nil => return Ok(items),
+            Value::Put0(c) if *c == cons => {}
+            v => return Err(unexpected("one of the markers", v)),
+        }
+        // then what the head and tail are, given to `s` to hold on to
+        let (head, tail) = match probe(t, &[Term::Put(nil), Term::S], &[nil])?.as_ref() {
+            Value::S2(head, tail) => (head.readback(), tail.readback()),
+            v => return Err(unexpected("`s` waiting on a head and a tail", v)),
+        };
+        let continuation = || Error("it holds a continuation".into());
+        let head = head.ok_or_else(continuation)?;
+        let n = to_nat(head).map_err(|e| Error(format!("item {}: {}", items.len(), e)))?;
+        items.push(n);
+        t = tail.ok_or_else(continuation)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::term::parse_term;
+
+    #[test]
+    fn numbers_round_trip() {
+        for n in [0, 1, 2, 3, 4, 7, 10, 255, 1000] {
+            assert_eq!(to_nat(nat(n)), Ok(n));
+        }
+    }
+
+    #[test]
+    fn booleans_round_trip() {
+        assert_eq!(to_bool(bool(true)), Ok(true));
+        assert_eq!(to_bool(bool(false)), Ok(false));
+    }
+
+    #[test]
+    fn lists_round_trip() {
+        assert_eq!(to_list(list([])), Ok(vec![]));
+        assert_eq!(to_list(list([nat(0), nat(1), nat(6)])), Ok(vec![0, 1, 6]));
+        assert_eq!(to_list(bytes(b"")), Ok(vec![]));
+        assert_eq!(to_list(bytes(b"hi")), Ok(vec![104, 105]));
+    }
+
+    #[test]
+    fn values_made_of_v_are_not_data() {
+        for source in ["v", "i", "`kv"] {
+            let t = Arc::new(parse_term(source).unwrap());
+            assert!(to_bool(t.clone()).is_err(), "{}", source);
+            assert!(to_list(t.clone()).is_err(), "{}", source);
+            // `i` is the numeral 1
+            if source != "i" {
+                assert!(to_nat(t).is_err(), "{}", source);
+            }
+        }
+    }
+
+    #[test]
+    fn values_that_print_are_not_data() {
+        let prints = Arc::new(parse_term("``s`k.xi").unwrap());
+        assert!(to_nat(prints).is_err());
+    }
+}
@@ -0,0 +1,203 @@
+use super::truncate;
+use crate::term::{Program, Span, Term};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+
+// Which parts of a program ever ran
+
+/// How many times each term was evaluated, and which terms were delayed
+/// by `d`.
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    // by term address
+    hits: HashMap<usize, (Arc<Term>, u64)>,
+    delayed: HashMap<usize, Arc<Term>>,
+}
+
+/// What became of a node.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mark {
+    Evaluated(u64),
+    /// Delayed by `d` and never forced
+    Delayed,
+    Unevaluated,
+}
+
+impl Coverage {
+    pub(crate) fn eval(&mut self, t: &Arc<Term>) {
+        let address = Arc::as_ptr(t) as usize;
+        self.hits.entry(address).or_insert_with(|| (t.clone(), 0)).1 += 1;
+    }
+
+    pub(crate) fn delay(&mut self, t: &Arc<Term>) {
+        let address = Arc::as_ptr(t) as usize;
+        self.delayed.entry(address).or_insert_with(|| t.clone());
+    }
+
+    /// Number of times a term was evaluated.
+    pub fn hits(&self, t: &Term) -> u64 {
+        self.hits
+            .get(&(t as *const Term as usize))
+            .map_or(0, |(_, n)| *n)
+    }
+
+    pub fn mark(&self, t: &Term) -> Mark {
+        match self.hits(t) {
+            0 if self.delayed.contains_key(&(t as *const Term as usize)) => Mark::Delayed,
+            0 => Mark::Unevaluated,
+            n => Mark::Evaluated(n),
+        }
+    }
+
+    // Every node of the program in source order, with its span and mark
+    fn nodes<'a>(&self, program: &'a Program) -> Vec<(&'a Arc<Term>, Span, Mark)> {
+        let mut nodes = Vec::new();
+        let mut todo = vec![&program.term];
+        while let Some(t) = todo.pop() {
+            if let Some(span) = program.span(t) {
+                nodes.push((t, span, self.mark(t)));
+            }
+            if let Term::App(t0, t1) = t.as_ref() {
+                todo.push(t1);
+                todo.push(t0);
+            }
+        }
+        nodes
+    }
+
+    // The outermost nodes that never ran. Nothing inside a node can run
+    // without the node itself running, so these cover all that did not.
+    fn misses(&self, program: &Program) -> Vec<(Span, Mark)> {
+        let mut misses: Vec<(Span, Mark)> = Vec::new();
+        for (_, span, mark) in self.nodes(program) {
+            let inside = misses.last().is_some_and(|(last, _)| span.end <= last.end);
+            if !matches!(mark, Mark::Evaluated(_)) && !inside {
+                misses.push((span, mark));
+            }
+        }
+        misses
+    }
+
+    fn summary(&self, program: &Program) -> String {
+        let nodes = self.nodes(program);
+        let evaluated = nodes
+            .iter()
+            .filter(|(_, _, mark)| matches!(mark, Mark::Evaluated(_)))
+            .count();
+        let delayed = nodes
+            .iter()
+            .filter(|(_, _, mark)| *mark == Mark::Delayed)
+            .count();
+        format!(
+            "Coverage: {} of {} terms evaluated ({:.2}%), {} delayed terms never forced",
+            evaluated,
+            nodes.len(),
+            100.0 * evaluated as f64 / nodes.len().max(1) as f64,
+            delayed,
+        )
+    }
+
+    /// The source with a line under each line of it marking what never ran,
+    /// `^` for terms never evaluated and `~` for terms delayed by `d` and
+    /// never forced, followed by the hit count of every term.
+    pub fn text(&self, program: &Program) -> String {
+        let source = &program.source;
+        let mut marks = vec![' '; source.len()];
+        for (span, mark) in self.misses(program) {
+            let c = if mark == Mark::Delayed { '~' } else { '^' };
+            for (i, _) in source[span.start..span.end].char_indices() {
+                marks[span.start + i] = c;
+            }
+        }
+
+        let mut out = String::new();
+        writeln!(out, "{}", self.summary(program)).unwrap();
+        let mut start = 0;
+        for line in source.split_inclusive('\n') {
+            let text = line.trim_end_matches(['\n', '\r']);
+            writeln!(out, "{}", text).unwrap();
+            let under: String = text
+                .char_indices()
+                .map(|(i, c)| {
+                    if c.is_whitespace() {
+                        c
+                    } else {
+                        marks[start + i]
+                    }
+                })
+                .collect();
+            if under.contains(['^', '~']) {
+                writeln!(out, "{}", under.trim_end()).unwrap();
+            }
+            start += line.len();
+        }
+
+        writeln!(out, "\nHits:").unwrap();
+        for (t, span, mark) in self.nodes(program) {
+            let hits = match mark {
+                Mark::Evaluated(n) => n.to_string(),
+                Mark::Delayed => "delayed".to_string(),
+                Mark::Unevaluated => "0".to_string(),
+            };
+            writeln!(
+                out,
+                "{:<9} {:>12}  {}",
+                span.to_string(),
+                hits,
+                truncate(&t.to_string(), 60)
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    /// The source as a web page with what never ran highlighted. Hovering
+    /// over a term shows how many times it was evaluated.
+    pub fn html(&self, program: &Program) -> String {
+        let source = &program.source;
+        // where each span opens and closes, in source order
+        let mut opens: HashMap<usize, Vec<String>> = HashMap::new();
+        let mut closes: HashMap<usize, usize> = HashMap::new();
+        for (_, span, mark) in self.nodes(program) {
+            let (class, title) = match mark {
+                Mark::Evaluated(n) => ("hit", format!("{}: {} hits", span, n)),
+                Mark::Delayed => ("delayed", format!("{}: delayed, never forced", span)),
+                Mark::Unevaluated => ("miss", format!("{}: never evaluated", span)),
+            };
+            opens
+                .entry(span.start)
+                .or_default()
+                .push(format!("<span class=\"{}\" title=\"{}\">", class, title));
+            *closes.entry(span.end).or_default() += 1;
+        }
+
+        let mut out = String::new();
+        out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Coverage</title>\n<style>\n");
+        out.push_str(".miss { background: #f99; }\n.delayed { background: #fd8; }\n");
+        out.push_str(".miss .miss, .delayed .miss, .delayed .delayed { background: none; }\n");
+        out.push_str("</style>\n</head>\n<body>\n");
+        writeln!(out, "<p>{}</p>\n<pre>", escape(&self.summary(program))).unwrap();
+        for (i, c) in source.char_indices() {
+            for _ in 0..closes.get(&i).copied().unwrap_or(0) {
+                out.push_str("</span>");
+            }
+            for open in opens.get(&i).into_iter().flatten() {
+                out.push_str(open);
+            }
+            out.push_str(&escape(c.encode_utf8(&mut [0; 4])));
+        }
+        for _ in 0..closes.get(&source.len()).copied().unwrap_or(0) {
+            out.push_str("</span>");
+        }
+        out.push_str("</pre>\n</body>\n</html>\n");
+        out
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
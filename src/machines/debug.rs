@@ -0,0 +1,581 @@
+use super::arc::{write_graph, Held, Kont, Share, State, StateFlag, Value};
+use super::interrupt::Interrupts;
+use super::render::Bounds;
+use super::snapshot::save;
+use super::stats::APPLICATIONS;
+use super::Limit;
+use crate::term::Program;
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+// A source-level debugger for the sharing machine
+
+const HELP: &str = "\
+Commands (enter repeats the last one):
+  stepi, si [N]          take N machine transitions (default 1)
+  step, s                run to the next source term evaluated
+  next, n                like step, but over the current application
+  finish, f              run until the current frame returns
+  continue, c            run to the next breakpoint
+  run, r                 run to the end, ignoring breakpoints
+  reverse-stepi, rsi [N] go back N machine transitions (default 1)
+  reverse-step, rs       go back to the previous source term evaluated
+  reverse-continue, rc   go back to the previous breakpoint or output
+  goto N                 go back or forward to step N
+  history                show how far back the debugger can go
+  break, b WHERE         stop at WHERE, which is one of
+                           L or L:C     evaluating a term on line L (at column C)
+                           apply NAME   applying a value, e.g. C0 for `c` firing
+                                        or D1T for a promise being forced
+                           output       after a character is printed
+                           step N       at step N
+                           depth N      when the continuation is deeper than N
+  watch WHERE            same as break
+  delete, d N            remove breakpoint N
+  breaks                 list breakpoints
+  frames, bt [N]         list the top N frames of the continuation (default 10)
+  print, p v|w|k|t       show the value, argument, continuation or term
+  state                  show the whole machine state
+  where                  show where the machine is
+  dot FILE               draw the values and frames held to FILE, for Graphviz
+  save FILE              write the machine state to FILE, to --resume later
+  quit, q                stop debugging
+Ctrl-C stops a command that is running and comes back here.";
+
+/// A condition to stop on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Breakpoint {
+    /// Evaluating a term that starts on this line, and at this column
+    At(usize, Option<usize>),
+    /// Applying a value of this kind, by position in `stats::APPLICATIONS`
+    Apply(usize),
+    /// Having printed a character
+    Output,
+    /// Having taken this many steps
+    Step(u64),
+    /// The continuation being deeper than this
+    Depth(usize),
+}
+
+impl Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Breakpoint::At(line, None) => write!(f, "line {}", line),
+            Breakpoint::At(line, Some(col)) => write!(f, "{}:{}", line, col),
+            Breakpoint::Apply(kind) => write!(f, "apply {}", APPLICATIONS[*kind]),
+            Breakpoint::Output => write!(f, "output"),
+            Breakpoint::Step(n) => write!(f, "step {}", n),
+            Breakpoint::Depth(n) => write!(f, "depth {}", n),
+        }
+    }
+}
+
+impl Breakpoint {
+    fn parse(args: &[&str]) -> Result<Self, String> {
+        match args {
+            ["apply", name] => APPLICATIONS
+                .iter()
+                .position(|kind| kind.eq_ignore_ascii_case(name))
+                .map(Breakpoint::Apply)
+                .ok_or_else(|| {
+                    format!(
+                        "no value is called {}; try one of {}",
+                        name,
+                        APPLICATIONS.join(" ")
+                    )
+                }),
+            ["output"] => Ok(Breakpoint::Output),
+            ["step", n] => n
+                .parse()
+                .map(Breakpoint::Step)
+                .map_err(|_| "expected a step number".into()),
+            ["depth", n] => n
+                .parse()
+                .map(Breakpoint::Depth)
+                .map_err(|_| "expected a depth".into()),
+            [at] => {
+                let bad = |_| "expected L or L:C".to_string();
+                let (line, col) = match at.split_once(':') {
+                    Some((line, col)) => (line, Some(col.parse().map_err(bad)?)),
+                    None => (*at, None),
+                };
+                Ok(Breakpoint::At(line.parse().map_err(bad)?, col))
+            }
+            _ => Err("expected L, L:C, apply NAME, output, step N or depth N".into()),
+        }
+    }
+
+    // Whether the state about to be stepped is at this breakpoint
+    fn hit<P: Share>(&self, program: &Program, state: &State<P>, printed: bool) -> bool {
+        match *self {
+            Breakpoint::At(line, col) => {
+                let span = state.term().and_then(|t| program.span(t));
+                state.flag() == StateFlag::Eval
+                    && span.is_some_and(|span| {
+                        span.line == line && col.is_none_or(|col| span.col == col)
+                    })
+            }
+            Breakpoint::Apply(kind) => {
+                state.flag() == StateFlag::ApplyV
+                    && state.v().is_some_and(|v| v.kind().index() == kind)
+            }
+            Breakpoint::Output => printed,
+            Breakpoint::Step(n) => state.steps() == n,
+            Breakpoint::Depth(n) => state.depth() > n,
+        }
+    }
+}
+
+// States to go back to, kept every `every` steps. Once what they hold
+// beyond the current state comes to more than `most` bytes, every other one
+// is dropped and they are kept half as often, so a long run is covered all
+// the way back at a coarser grain. What they hold is counted by walking
+// them, now and then, so it can go somewhat over before it is noticed.
+struct History<P: Share> {
+    states: Vec<State<P>>,
+    every: u64,
+    most: usize,
+    // bytes held as last counted, steps since then, and parts walked then
+    bytes: usize,
+    since: u64,
+    walked: usize,
+}
+
+impl<P: Share> History<P> {
+    fn record(&mut self, state: &mut State<P>) {
+        // the program is deterministic, so states kept past this one after
+        // going back are still right
+        if !state.steps().is_multiple_of(self.every)
+            || self
+                .states
+                .last()
+                .is_some_and(|s| s.steps() >= state.steps())
+        {
+            return;
+        }
+        self.states.push(state.checkpoint());
+        self.since += self.every;
+        // a walk every so many steps, so walking costs a few parts a step
+        if self.since < (self.walked / 4).max(64) as u64 {
+            return;
+        }
+        self.measure(state);
+        let over = self.bytes > self.most;
+        while self.bytes > self.most && self.states.len() > 1 {
+            self.every *= 2;
+            let every = self.every;
+            self.states.retain(|s| s.steps().is_multiple_of(every));
+            self.measure(state);
+        }
+        if over {
+            state.recount();
+        }
+    }
+
+    // Counts the bytes the states hold that the current one does not: the
+    // values and frames only they hold, and the states themselves
+    fn measure(&mut self, state: &State<P>) {
+        let mut seen = HashSet::new();
+        let current = state.held(&mut seen);
+        let mut held = Held::default();
+        for s in &self.states {
+            let more = s.held(&mut seen);
+            held.values += more.values;
+            held.frames += more.frames;
+        }
+        // each value and frame also has its pointer's counts
+        let header = 2 * size_of::<usize>();
+        self.bytes = held.values * (size_of::<Value<P>>() + header)
+            + held.frames * (size_of::<Kont<P>>() + header)
+            + self.states.len() * size_of::<State<P>>();
+        self.walked = current.values + current.frames + held.values + held.frames;
+        self.since = 0;
+    }
+
+    // The latest state kept at or before this step
+    fn before(&self, step: u64) -> Option<&State<P>> {
+        let i = self.states.partition_point(|s| s.steps() <= step);
+        i.checked_sub(1).map(|i| &self.states[i])
+    }
+}
+
+pub struct Debugger<'a, P: Share> {
+    program: &'a Program,
+    breakpoints: Vec<Option<Breakpoint>>,
+    last: String,
+    history: History<P>,
+    bounds: Bounds,
+    interrupts: Option<&'a Interrupts>,
+}
+
+type Finished<P> = Result<<P as Share>::Ptr<super::arc::Value<P>>, Limit>;
+
+impl<'a, P: Share> Debugger<'a, P> {
+    /// A debugger keeping past states to go back to, holding about `bytes`
+    /// bytes at most beyond what the current state holds.
+    pub fn new(program: &'a Program, bytes: usize) -> Self {
+        Debugger {
+            program,
+            breakpoints: Vec::new(),
+            last: "stepi".to_string(),
+            history: History {
+                states: Vec::new(),
+                every: 1,
+                most: bytes,
+                bytes: 0,
+                since: 0,
+                walked: 0,
+            },
+            bounds: Bounds::SHORT,
+            interrupts: None,
+        }
+    }
+
+    /// Show terms, values and continuations within these bounds.
+    pub fn bounds(mut self, bounds: Bounds) -> Self {
+        self.bounds = bounds;
+        self
+    }
+
+    /// Stops running and goes back to the prompt on Ctrl-C.
+    pub fn interrupts(mut self, interrupts: Option<&'a Interrupts>) -> Self {
+        self.interrupts = interrupts;
+        self
+    }
+
+    /// Takes at least one step, then runs until `done` holds, a breakpoint
+    /// is hit, Ctrl-C comes in or the program finishes or goes over a limit.
+    pub fn advance(
+        &mut self,
+        state: &mut State<P>,
+        breaks: bool,
+        mut done: impl FnMut(&State<P>) -> bool,
+    ) -> Option<Finished<P>> {
+        // a Ctrl-C typed at the prompt does not stop what comes next
+        if let Some(interrupts) = self.interrupts {
+            interrupts.take_stop();
+        }
+        loop {
+            if let Some(v) = state.extract() {
+                return Some(Ok(v));
+            }
+            if let Some(limit) = state.check() {
+                return Some(Err(limit));
+            }
+            self.history.record(state);
+            let output = state.output();
+            state.step();
+            if let Some(v) = state.extract() {
+                return Some(Ok(v));
+            }
+            if done(state) {
+                return None;
+            }
+            if let Some(interrupts) = self.interrupts {
+                if interrupts.take_report() {
+                    eprintln!("\n{}", state.summary());
+                }
+                if interrupts.take_stop() {
+                    println!("\nInterrupted");
+                    return None;
+                }
+            }
+            if !breaks {
+                continue;
+            }
+            let printed = state.output() > output;
+            for (i, breakpoint) in self.breakpoints.iter().enumerate() {
+                if let Some(breakpoint) = breakpoint.filter(|b| b.hit(self.program, state, printed))
+                {
+                    println!("Breakpoint {}: {}", i + 1, breakpoint);
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// Takes the machine to step `n`, going back to the latest state kept
+    /// before it if need be. Breakpoints are not checked on the way.
+    pub fn goto(&mut self, state: &mut State<P>, n: u64) -> Option<Finished<P>> {
+        if n < state.steps() {
+            let checkpoint = self.history.before(n)?;
+            state.restore(checkpoint);
+        }
+        if n > state.steps() {
+            return self.advance(state, false, |s| s.steps() >= n);
+        }
+        None
+    }
+
+    // The last step before the current one at which `hit` held for the state
+    // about to be stepped, given whether the step into it printed. Found by
+    // running again from the states kept, latest first.
+    fn find_back(&self, state: &State<P>, hit: impl Fn(&State<P>, bool) -> bool) -> Option<u64> {
+        let end = state.steps();
+        let mut before = end;
+        while let Some(checkpoint) = before.checked_sub(1).and_then(|n| self.history.before(n)) {
+            // a copy of the machine, so nothing is printed twice
+            let mut replay = state.checkpoint();
+            replay.restore(checkpoint);
+            let mut found = None;
+            while replay.steps() < before && replay.extract().is_none() {
+                let output = replay.output();
+                replay.step();
+                if replay.steps() < end && hit(&replay, replay.output() > output) {
+                    found = Some(replay.steps());
+                }
+            }
+            if found.is_some() {
+                return found;
+            }
+            before = checkpoint.steps();
+        }
+        None
+    }
+
+    // Goes back to the last step `hit` held at, or to the start
+    fn back(&mut self, state: &mut State<P>, hit: impl Fn(&State<P>, bool) -> bool) {
+        match self.find_back(state, hit) {
+            Some(n) => {
+                self.goto(state, n);
+            }
+            None => {
+                println!("Back at the start");
+                self.goto(state, 0);
+            }
+        }
+    }
+
+    /// Where the machine is, with the source line it is on.
+    pub fn show(&self, state: &State<P>) {
+        println!("{}", state.summary());
+        let span = state.term().and_then(|t| self.program.span(t));
+        if let Some(span) = span {
+            let line = self.program.source.lines().nth(span.line - 1).unwrap_or("");
+            println!("{:>5} | {}", span.line, line);
+            println!("{:>5} | {:>width$}", "", "^", width = span.col);
+        }
+    }
+
+    fn frames(&self, state: &State<P>, count: usize) {
+        let (wide, narrow) = (self.bounds.narrow(60), self.bounds.narrow(30));
+        let mut current = state.k();
+        let mut i = 0;
+        while let Some(k) = current {
+            if i == count {
+                println!("... {} more", state.depth() - i);
+                break;
+            }
+            let frame = match k.as_ref() {
+                Kont::BindT(t, _) => {
+                    let at = self
+                        .program
+                        .span(t)
+                        .map_or(String::new(), |span| format!(" at {}", span));
+                    format!("BindT [{}]{}", wide.render(t.as_ref()), at)
+                }
+                Kont::BindV(v, _) => format!("BindV {}", wide.render(v.as_ref())),
+                Kont::BindW(w, _) => format!("BindW {}", wide.render(w.as_ref())),
+                Kont::SWait(v1, v, _) => format!(
+                    "SWait {} {}",
+                    narrow.render(v1.as_ref()),
+                    narrow.render(v.as_ref())
+                ),
+            };
+            println!("#{:<4} {}", i, frame);
+            current = k.next().as_ref();
+            i += 1;
+        }
+        if i == 0 {
+            println!("No frames");
+        }
+    }
+
+    fn print(&self, state: &State<P>, what: &str) {
+        match what {
+            "v" => match state.v() {
+                Some(v) => println!("{}", self.bounds.render(v.as_ref())),
+                None => println!("No value"),
+            },
+            "w" => match state.w() {
+                Some(w) => println!("{}", self.bounds.render(w.as_ref())),
+                None => println!("No argument"),
+            },
+            "k" => match state.k() {
+                Some(k) => println!("{}", self.bounds.render(k.as_ref())),
+                None => println!("()"),
+            },
+            "t" => match state.term() {
+                Some(t) => println!("[{}]", self.bounds.render(t.as_ref())),
+                None => println!("No term"),
+            },
+            _ => println!("Expected one of v, w, k or t"),
+        }
+    }
+
+    /// Reads commands until the program finishes or the user quits, which
+    /// gives `None`.
+    pub fn run(&mut self, state: &mut State<P>) -> Option<Finished<P>> {
+        let finished = self.session(state);
+        // what only the states kept held is gone now
+        self.history.states.clear();
+        state.recount();
+        finished
+    }
+
+    fn session(&mut self, state: &mut State<P>) -> Option<Finished<P>> {
+        self.show(state);
+        println!("Type `help` for commands.");
+        let stdin = std::io::stdin();
+        loop {
+            print!("(unabs) ");
+            std::io::stdout().flush().unwrap();
+            let mut input = String::new();
+            if stdin.lock().read_line(&mut input).unwrap() == 0 {
+                return None;
+            }
+            let input = match input.trim() {
+                "" => self.last.clone(),
+                input => input.to_string(),
+            };
+            self.last = input.clone();
+            let words: Vec<&str> = input.split_whitespace().collect();
+            let depth = state.depth();
+            let program = self.program;
+            let finished = match words.as_slice() {
+                ["help" | "h"] => {
+                    println!("{}", HELP);
+                    continue;
+                }
+                ["stepi" | "si", rest @ ..] => {
+                    let Ok(n) = rest.first().map_or(Ok(1), |n| n.parse::<u64>()) else {
+                        println!("Expected a number of steps");
+                        continue;
+                    };
+                    let end = state.steps() + n;
+                    self.advance(state, true, |s| s.steps() >= end)
+                }
+                ["step" | "s"] => self.advance(state, true, |s| at_source(program, s)),
+                ["next" | "n"] => {
+                    self.advance(state, true, |s| at_source(program, s) && s.depth() <= depth)
+                }
+                ["finish" | "f"] => self.advance(state, true, |s| s.depth() < depth),
+                ["continue" | "c"] => self.advance(state, true, |_| false),
+                ["run" | "r"] => self.advance(state, false, |_| false),
+                ["reverse-stepi" | "rsi", rest @ ..] => {
+                    let Ok(n) = rest.first().map_or(Ok(1), |n| n.parse::<u64>()) else {
+                        println!("Expected a number of steps");
+                        continue;
+                    };
+                    self.goto(state, state.steps().saturating_sub(n))
+                }
+                ["reverse-step" | "rs"] => {
+                    self.back(state, |s, _| at_source(program, s));
+                    None
+                }
+                ["reverse-continue" | "rc"] => {
+                    let breakpoints = self.breakpoints.clone();
+                    self.back(state, |s, printed| {
+                        printed
+                            || breakpoints
+                                .iter()
+                                .flatten()
+                                .any(|b| b.hit(program, s, printed))
+                    });
+                    None
+                }
+                ["goto", n] => {
+                    let Ok(n) = n.parse() else {
+                        println!("Expected a step number");
+                        continue;
+                    };
+                    self.goto(state, n)
+                }
+                ["history"] => {
+                    let history = &self.history;
+                    match history.states.first() {
+                        Some(first) => println!(
+                            "{} states kept, one every {} steps, from step {}, holding about {} KiB",
+                            history.states.len(),
+                            history.every,
+                            first.steps(),
+                            history.bytes / 1024
+                        ),
+                        None => println!("No states kept yet"),
+                    }
+                    continue;
+                }
+                ["break" | "b" | "watch", args @ ..] => {
+                    match Breakpoint::parse(args) {
+                        Ok(breakpoint) => {
+                            self.breakpoints.push(Some(breakpoint));
+                            println!("Breakpoint {}: {}", self.breakpoints.len(), breakpoint);
+                        }
+                        Err(e) => println!("{}", e),
+                    }
+                    continue;
+                }
+                ["delete" | "d", n] => {
+                    match n
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|n| self.breakpoints.get_mut(n.wrapping_sub(1)))
+                    {
+                        Some(breakpoint) => *breakpoint = None,
+                        None => println!("No breakpoint {}", n),
+                    }
+                    continue;
+                }
+                ["breaks"] => {
+                    for (i, breakpoint) in self.breakpoints.iter().enumerate() {
+                        if let Some(breakpoint) = breakpoint {
+                            println!("{:>3}  {}", i + 1, breakpoint);
+                        }
+                    }
+                    continue;
+                }
+                ["frames" | "bt", rest @ ..] => {
+                    let count = rest.first().and_then(|n| n.parse().ok()).unwrap_or(10);
+                    self.frames(state, count);
+                    continue;
+                }
+                ["print" | "p", what] => {
+                    self.print(state, what);
+                    continue;
+                }
+                ["state"] => {
+                    println!("{}", self.bounds.render(state));
+                    continue;
+                }
+                ["where"] => {
+                    self.show(state);
+                    continue;
+                }
+                ["dot", path] => {
+                    write_graph(state, self.program, Path::new(path));
+                    continue;
+                }
+                ["save", path] => {
+                    save(&state.snapshot(Some(self.program)), Path::new(path));
+                    continue;
+                }
+                ["quit" | "q"] => return None,
+                _ => {
+                    println!("Unknown command `{}`; type `help` for commands", input);
+                    continue;
+                }
+            };
+            if finished.is_some() {
+                return finished;
+            }
+            self.show(state);
+        }
+    }
+}
+
+// Whether the machine is about to evaluate a term of the program
+fn at_source<P: Share>(program: &Program, state: &State<P>) -> bool {
+    state.flag() == StateFlag::Eval && state.term().is_some_and(|t| program.span(t).is_some())
+}
@@ -0,0 +1,197 @@
+use super::render::Bounds;
+use super::snapshot::{Config, Error, Id, Kont, Node, Snapshot, Value};
+use std::fmt::Write;
+
+// The values and frames a state holds, as a Graphviz graph
+//
+// Each value, frame and term is drawn once, however many times it is held,
+// so what the sharing machine shares shows up as nodes with several edges
+// coming in, which are filled. Values are ellipses, frames are boxes, and
+// terms, which hold no values, are notes labelled with their text. A `c`
+// continuation is a red octagon pointing at the frames it captured, and a
+// promise made by `d` a blue ellipse pointing at its term.
+
+/// Longest term label, in characters.
+const TERM: usize = 30;
+
+/// The snapshot as a DOT graph.
+pub fn graph(snapshot: &Snapshot) -> Result<String, Error> {
+    let program = snapshot.program()?;
+    let terms = snapshot.terms(program.as_ref())?;
+    let mut edges: Vec<(String, Id, &str, &str)> = Vec::new();
+    let mut edge = |from: String, to: Id, label: &'static str, style: &'static str| {
+        edges.push((from, to, label, style))
+    };
+    let state = || "state".to_string();
+    let k = match snapshot.config {
+        Config::Eval(t, k) => {
+            edge(state(), t, "t", "");
+            k
+        }
+        Config::ApplyT(v, t, k) => {
+            edge(state(), v, "v", "");
+            edge(state(), t, "t", "");
+            k
+        }
+        Config::ApplyV(v, w, k) => {
+            edge(state(), v, "v", "");
+            edge(state(), w, "w", "");
+            k
+        }
+        Config::ApplyK(k, v) => {
+            edge(state(), v, "v", "");
+            k
+        }
+    };
+    if let Some(k) = k {
+        edge(state(), k, "k", "");
+    }
+    let promise = "color=blue, style=dashed";
+    let captured = "color=red";
+    let next = "style=bold";
+    for (id, node) in snapshot.nodes.iter().enumerate() {
+        let from = || format!("n{}", id);
+        match node {
+            Node::Value(value) => match *value {
+                Value::S1(x) | Value::K1(x) | Value::D1V(x) => edge(from(), x, "", ""),
+                Value::S2(x, y) => {
+                    edge(from(), x, "x", "");
+                    edge(from(), y, "y", "");
+                }
+                Value::D1T(t) => edge(from(), t, "", promise),
+                Value::C1(Some(k)) => edge(from(), k, "", captured),
+                _ => {}
+            },
+            Node::Kont(kont) => {
+                let k = match *kont {
+                    Kont::BindT(t, k) => {
+                        edge(from(), t, "t", "");
+                        k
+                    }
+                    Kont::BindV(v, k) => {
+                        edge(from(), v, "v", "");
+                        k
+                    }
+                    Kont::BindW(w, k) => {
+                        edge(from(), w, "w", "");
+                        k
+                    }
+                    Kont::SWait(v1, v, k) => {
+                        edge(from(), v1, "v1", "");
+                        edge(from(), v, "v", "");
+                        k
+                    }
+                };
+                if let Some(k) = k {
+                    edge(from(), k, "", next);
+                }
+            }
+            // the parts of a term are in its label
+            Node::Atom(_) | Node::App(..) | Node::At(_) => {}
+        }
+    }
+
+    let mut held = vec![0; snapshot.nodes.len()];
+    for (_, to, _, _) in &edges {
+        held[*to] += 1;
+    }
+    let mut out = String::from("digraph state {\n  node [fontname=\"monospace\"];\n");
+    let config = match snapshot.config {
+        Config::Eval(..) => "Eval",
+        Config::ApplyT(..) => "ApplyT",
+        Config::ApplyV(..) => "ApplyV",
+        Config::ApplyK(..) => "ApplyK",
+    };
+    writeln!(
+        out,
+        "  state [shape=box, style=bold, label=\"{}\\nafter {} rules\"];",
+        config, snapshot.steps
+    )
+    .unwrap();
+    for (id, node) in snapshot.nodes.iter().enumerate() {
+        if held[id] == 0 {
+            continue;
+        }
+        let (shape, label, color) = match node {
+            Node::Value(value) => {
+                let label = match value {
+                    Value::I0 => "i".to_string(),
+                    Value::S0 => "s".to_string(),
+                    Value::K0 => "k".to_string(),
+                    Value::V0 => "v".to_string(),
+                    Value::D0 => "d".to_string(),
+                    Value::C0 => "c".to_string(),
+                    Value::Put0('\n') => "r".to_string(),
+                    Value::Put0(c) => format!(".{}", c),
+                    Value::S1(_) => "S1".to_string(),
+                    Value::S2(..) => "S2".to_string(),
+                    Value::K1(_) => "K1".to_string(),
+                    Value::D1T(_) => "D1T".to_string(),
+                    Value::D1V(_) => "D1V".to_string(),
+                    Value::C1(None) => "C1 ()".to_string(),
+                    Value::C1(Some(_)) => "C1".to_string(),
+                };
+                match value {
+                    Value::C1(_) => ("octagon", label, " color=red,"),
+                    Value::D1T(_) => ("ellipse", label, " color=blue,"),
+                    _ => ("ellipse", label, ""),
+                }
+            }
+            Node::Kont(kont) => {
+                let label = match kont {
+                    Kont::BindT(..) => "BindT",
+                    Kont::BindV(..) => "BindV",
+                    Kont::BindW(..) => "BindW",
+                    Kont::SWait(..) => "SWait",
+                };
+                ("box", label.to_string(), "")
+            }
+            Node::Atom(_) | Node::App(..) | Node::At(_) => {
+                let t = terms[id].as_deref().unwrap();
+                let mut label = Bounds::SHORT.narrow(TERM).render(t);
+                if let Some(span) = program.as_ref().and_then(|program| program.span(t)) {
+                    write!(label, "\n@{}", span).unwrap();
+                }
+                ("note", label, "")
+            }
+        };
+        let fill = if held[id] > 1 {
+            " style=filled, fillcolor=lightyellow,"
+        } else {
+            ""
+        };
+        writeln!(
+            out,
+            "  n{} [shape={},{}{} label=\"{}\"];",
+            id,
+            shape,
+            color,
+            fill,
+            escape(&label)
+        )
+        .unwrap();
+    }
+    for (from, to, label, style) in &edges {
+        let attributes: Vec<String> = [
+            (!label.is_empty()).then(|| format!("label=\"{}\"", label)),
+            (!style.is_empty()).then(|| style.to_string()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if attributes.is_empty() {
+            writeln!(out, "  {} -> n{};", from, to).unwrap();
+        } else {
+            writeln!(out, "  {} -> n{} [{}];", from, to, attributes.join(", ")).unwrap();
+        }
+    }
+    out.push_str("}\n");
+    Ok(out)
+}
+
+// s in a quoted DOT string, with line breaks as `\n`
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+// Stacks sampled from a running machine, for flamegraph tools
+
+/// Samples of the continuation, counted by stack.
+#[derive(Debug, Clone, Default)]
+pub struct Flamegraph {
+    stacks: HashMap<String, u64>,
+    samples: u64,
+}
+
+impl Flamegraph {
+    /// Records a stack, outermost frame first.
+    pub fn add(&mut self, frames: impl IntoIterator<Item = String>) {
+        let stack: Vec<String> = frames.into_iter().map(|frame| clean(&frame)).collect();
+        *self.stacks.entry(stack.join(";")).or_insert(0) += 1;
+        self.samples += 1;
+    }
+
+    /// Number of stacks recorded.
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    /// One line per stack, with frames separated by `;` and the number of
+    /// samples at the end, as read by `flamegraph.pl` and `inferno`.
+    pub fn folded(&self) -> String {
+        let mut stacks: Vec<_> = self.stacks.iter().collect();
+        stacks.sort();
+        let mut out = String::new();
+        for (stack, n) in stacks {
+            writeln!(out, "{} {}", stack, n).unwrap();
+        }
+        out
+    }
+}
+
+// Frame names may not hold the separators of the format
+fn clean(frame: &str) -> String {
+    frame
+        .chars()
+        .map(|c| {
+            if c == ';' || c.is_whitespace() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,89 @@
+use super::output;
+use signal_hook::consts::SIGINT;
+use signal_hook::flag;
+use std::io::{BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// Stopping a long run to see where it is
+
+/// Steps between two looks at whether a signal came in.
+pub const POLL: u64 = 1 << 12;
+
+/// Flags raised by Ctrl-C, to stop and ask what to do, and by SIGUSR1, to
+/// report where the run is and go on.
+pub struct Interrupts {
+    stop: Arc<AtomicBool>,
+    report: Arc<AtomicBool>,
+}
+
+/// What to do with a run stopped by Ctrl-C.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Choice {
+    Resume,
+    /// Step through the program from where it stopped
+    Inspect,
+    /// Write the state down to take it up later, and stop
+    Save,
+    Quit,
+}
+
+impl Interrupts {
+    /// Handles Ctrl-C and SIGUSR1 from now on. A second Ctrl-C before the
+    /// first was dealt with ends the program as usual, so a stuck run can
+    /// still be killed.
+    pub fn install() -> std::io::Result<Self> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let report = Arc::new(AtomicBool::new(false));
+        flag::register_conditional_shutdown(SIGINT, 130, stop.clone())?;
+        flag::register(SIGINT, stop.clone())?;
+        #[cfg(unix)]
+        flag::register(signal_hook::consts::SIGUSR1, report.clone())?;
+        Ok(Interrupts { stop, report })
+    }
+
+    /// Whether a signal came in since it was last dealt with.
+    pub fn pending(&self) -> bool {
+        self.stop.load(Ordering::Relaxed) || self.report.load(Ordering::Relaxed)
+    }
+
+    /// Whether Ctrl-C came in, clearing it.
+    pub fn take_stop(&self) -> bool {
+        self.stop.swap(false, Ordering::Relaxed)
+    }
+
+    /// Whether SIGUSR1 came in, clearing it.
+    pub fn take_report(&self) -> bool {
+        self.report.swap(false, Ordering::Relaxed)
+    }
+
+    /// Asks what to do after Ctrl-C, offering to save if there is somewhere
+    /// to. Ctrl-C again while asking ends the program.
+    pub fn ask(&self, save: bool) -> Choice {
+        output::flush();
+        let stdin = std::io::stdin();
+        let choice = loop {
+            if save {
+                eprint!("[r]esume, [i]nspect, [s]ave or [q]uit? ");
+            } else {
+                eprint!("[r]esume, [i]nspect or [q]uit? ");
+            }
+            std::io::stderr().flush().unwrap();
+            let mut input = String::new();
+            if stdin.lock().read_line(&mut input).unwrap_or(0) == 0 {
+                break Choice::Quit;
+            }
+            match input.trim() {
+                "r" | "resume" => break Choice::Resume,
+                "i" | "inspect" => break Choice::Inspect,
+                "s" | "save" if save => break Choice::Save,
+                "q" | "quit" => break Choice::Quit,
+                _ => {}
+            }
+        };
+        if choice == Choice::Resume {
+            self.stop.store(false, Ordering::Relaxed);
+        }
+        choice
+    }
+}
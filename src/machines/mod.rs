@@ -1,3 +1,212 @@
+use crate::term::Term;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
 pub mod anaive;
 pub mod arc;
-// pub mod v;
\ No newline at end of file
+pub mod codec;
+pub mod coverage;
+pub mod debug;
+pub mod dot;
+pub mod flame;
+pub mod interrupt;
+pub mod output;
+pub mod profile;
+pub mod provenance;
+pub mod readback;
+pub mod render;
+pub mod repl;
+pub mod slice;
+pub mod snapshot;
+pub mod stats;
+pub mod trace;
+// pub mod v;
+
+/// How to run a program, as asked for on the command line.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Step through the program by hand, or debug it on the arc machine
+    pub interactive: bool,
+    /// Most bytes the debugger's past states may hold beyond the current one
+    pub history: usize,
+    /// How much of terms, values and continuations the stepper and debugger show
+    pub bounds: render::Bounds,
+    /// Apply effect-free values in fewer steps (arc only)
+    pub simplify: bool,
+    /// Caps on the run (arc only)
+    pub limits: Limits,
+    /// Print stats at the end, in this format
+    pub stats: Option<stats::Format>,
+    /// Profile the run, writing the report to this file or to stderr (arc only)
+    pub profile: Option<Option<PathBuf>>,
+    /// Write stacks sampled from the continuation to this file (arc only)
+    pub flamegraph: Option<PathBuf>,
+    /// Steps between two samples
+    pub sample_every: u64,
+    /// Write which terms ran to this file, as HTML if it ends in `.html` (arc only)
+    pub coverage: Option<PathBuf>,
+    /// Write where each printed character came from to this file (arc only)
+    pub provenance: Option<PathBuf>,
+    /// Show what the result or a printed character depends on
+    pub slice: Option<slice::Target>,
+    /// Write the machine state to this file if the run stops early
+    pub snapshot: Option<PathBuf>,
+    /// Write every transition to this file as a line of JSON (arc only)
+    pub trace: Option<PathBuf>,
+    /// Write the values and frames of the last state to this file as a Graphviz graph (arc only)
+    pub dot: Option<PathBuf>,
+    /// Print the result as a term that evaluates to it
+    pub readback: bool,
+    /// Print the result as the data it encodes
+    pub result_as: Option<codec::Kind>,
+    /// Where the result goes
+    pub result: ResultMode,
+}
+
+/// Where the final value is printed, apart from what the program prints.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ResultMode {
+    /// Nowhere
+    None,
+    /// After what the program prints
+    #[default]
+    Stdout,
+    Stderr,
+    /// To stderr, as a line of JSON
+    Json,
+}
+
+// Prints the result where asked
+pub(crate) fn print_result<V: Display, T: Into<Arc<Term>>>(
+    value: &V,
+    options: &Options,
+    term: impl FnOnce(&V) -> Option<T>,
+) {
+    match options.result {
+        ResultMode::None => {}
+        ResultMode::Stdout => println!("Result:\n{}", shown(value, options, term)),
+        ResultMode::Stderr => eprintln!("Result:\n{}", shown(value, options, term)),
+        ResultMode::Json => eprintln!("{}", json(value, options, term)),
+    }
+}
+
+// The result as a JSON object: the value, and, if asked for, the term it
+// reads back as and the data it encodes, or why there are none
+fn json<V: Display, T: Into<Arc<Term>>>(
+    value: &V,
+    options: &Options,
+    term: impl FnOnce(&V) -> Option<T>,
+) -> String {
+    let mut out = format!("{{\"value\":{}", trace::string(&value.to_string()));
+    if options.readback || options.result_as.is_some() {
+        let t: Option<Arc<Term>> = term(value).map(Into::into);
+        if options.readback {
+            let t = t.as_ref().map(|t| trace::string(&t.to_string()));
+            out.push_str(&format!(",\"term\":{}", trace::or_null(t)));
+        }
+        let data = match (options.result_as, t) {
+            (None, _) => None,
+            (Some(_), None) => Some(Err("it holds a continuation".to_string())),
+            (Some(kind), Some(t)) => Some(kind.decode(t).map_err(|e| e.0)),
+        };
+        match data {
+            Some(Ok(data)) => out.push_str(&format!(",\"data\":{}", data)),
+            Some(Err(e)) => {
+                out.push_str(&format!(",\"data\":null,\"error\":{}", trace::string(&e)))
+            }
+            None => {}
+        }
+    }
+    out.push('}');
+    out
+}
+
+// The result as printed: decoded or read back into a term if asked and if
+// it can be, and as a value otherwise
+pub(crate) fn shown<V: Display, T: Into<Arc<Term>>>(
+    value: &V,
+    options: &Options,
+    term: impl FnOnce(&V) -> Option<T>,
+) -> String {
+    if !options.readback && options.result_as.is_none() {
+        return value.to_string();
+    }
+    let Some(t) = term(value) else {
+        eprintln!("The result holds a continuation, which no term evaluates to");
+        return value.to_string();
+    };
+    let t: Arc<Term> = t.into();
+    match options.result_as {
+        Some(kind) => kind.decode(t).unwrap_or_else(|e| {
+            eprintln!("The result is not {}: {}", kind, e);
+            value.to_string()
+        }),
+        None => t.to_string(),
+    }
+}
+
+// Cuts s down to at most max characters
+pub(crate) fn truncate(s: &str, max: usize) -> String {
+    match s.char_indices().nth(max) {
+        Some((i, _)) => format!("{}...", &s[..i]),
+        None => s.to_string(),
+    }
+}
+
+/// Where a bounded run stopped.
+#[derive(Debug)]
+pub enum Outcome<S, V> {
+    /// The program finished with this value.
+    Finished(V),
+    /// The program is still running; the state can be resumed later.
+    Suspended(S),
+    /// The program went over one of its limits; the state is as it was then.
+    Exceeded(Limit, S),
+}
+
+/// Caps on what a run may use. `None` leaves a resource unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    /// Transitions taken
+    pub steps: Option<u64>,
+    /// Values alive at once
+    pub values: Option<usize>,
+    /// Frames in the continuation
+    pub depth: Option<usize>,
+    /// Bytes printed by the program
+    pub output: Option<u64>,
+    /// Wall-clock time
+    pub time: Option<Duration>,
+}
+
+impl Limits {
+    pub fn is_unlimited(&self) -> bool {
+        *self == Limits::default()
+    }
+}
+
+/// The limit a run went over, with how much it had used at that point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Steps(u64),
+    Values(usize),
+    Depth(usize),
+    Output(u64),
+    Time(Duration),
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Limit::Steps(n) => write!(f, "step limit exceeded ({} steps)", n),
+            Limit::Values(n) => write!(f, "value limit exceeded ({} live values)", n),
+            Limit::Depth(n) => write!(f, "depth limit exceeded ({} frames)", n),
+            Limit::Output(n) => write!(f, "output limit exceeded ({} bytes)", n),
+            Limit::Time(t) => write!(f, "time limit exceeded ({:?})", t),
+        }
+    }
+}
+
+impl std::error::Error for Limit {}
@@ -0,0 +1,96 @@
+use std::cell::RefCell;
+use std::io::{ErrorKind, Write};
+
+// What programs print, on its way to stdout
+//
+// Writing out each character as it is printed costs a system call per
+// character, which is most of the time print-heavy programs take. So what
+// is printed is kept here and written out as the flush policy says. Anyone
+// who prints to stdout themselves, or waits on input, flushes first so the
+// program's output is not held back or out of order, and what is left is
+// written out when the program ends.
+
+/// Most bytes held before they are written out under `Flush::Full`.
+const BUFFER: usize = 1 << 13;
+
+/// When what a program prints is written out.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Flush {
+    /// Each character as it is printed
+    #[default]
+    None,
+    /// At the end of each line
+    Line,
+    /// When the buffer is full
+    Full,
+}
+
+struct Output {
+    policy: Flush,
+    buf: Vec<u8>,
+}
+
+thread_local! {
+    static OUTPUT: RefCell<Output> = const {
+        RefCell::new(Output {
+            policy: Flush::None,
+            buf: Vec::new(),
+        })
+    };
+}
+
+impl Output {
+    fn write(&mut self) {
+        let mut stdout = std::io::stdout().lock();
+        let written = stdout.write_all(&self.buf).and_then(|()| stdout.flush());
+        self.buf.clear();
+        match written {
+            Ok(()) => {}
+            // whatever reads the output, say `head`, wants no more of it
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => std::process::exit(0),
+            Err(e) => panic!("failed printing to stdout: {}", e),
+        }
+    }
+}
+
+// A backstop for a thread that ends without flushing
+impl Drop for Output {
+    fn drop(&mut self) {
+        let mut stdout = std::io::stdout().lock();
+        let _ = stdout.write_all(&self.buf).and_then(|()| stdout.flush());
+    }
+}
+
+/// Writes out what was printed so far, then follows `policy` from now on.
+pub fn set(policy: Flush) {
+    OUTPUT.with_borrow_mut(|out| {
+        out.write();
+        out.policy = policy;
+    })
+}
+
+/// Prints a character for the program.
+pub fn put(c: char) {
+    OUTPUT.with_borrow_mut(|out| {
+        let mut bytes = [0; 4];
+        out.buf
+            .extend_from_slice(c.encode_utf8(&mut bytes).as_bytes());
+        let now = match out.policy {
+            Flush::None => true,
+            Flush::Line => c == '\n',
+            Flush::Full => out.buf.len() >= BUFFER,
+        };
+        if now {
+            out.write();
+        }
+    })
+}
+
+/// Writes out what was printed so far.
+pub fn flush() {
+    OUTPUT.with_borrow_mut(|out| {
+        if !out.buf.is_empty() {
+            out.write();
+        }
+    })
+}
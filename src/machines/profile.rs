@@ -0,0 +1,143 @@
+use super::truncate;
+use crate::term::{Program, Term};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+
+// Credits machine steps to the source terms they work on
+
+/// Steps credited to each term. Evaluating a term is credited to the term,
+/// and applying a value to the term that made it: the primitive it was
+/// evaluated from, or the term whose work built it at runtime.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    // by term address; every term that was ever current has an entry
+    counts: HashMap<usize, (Arc<Term>, u64)>,
+    // the term that pushed each live frame, by frame address. Entries for
+    // dead frames linger, but are overwritten when their address is reused.
+    origins: HashMap<usize, usize>,
+    // the term that made each value, by value address, lingering the same way
+    values: HashMap<usize, usize>,
+    current: Option<usize>,
+    total: u64,
+}
+
+impl Profile {
+    pub(crate) fn enter(&mut self, t: &Arc<Term>) {
+        let address = Arc::as_ptr(t) as usize;
+        self.counts.entry(address).or_insert_with(|| (t.clone(), 0));
+        self.current = Some(address);
+    }
+
+    pub(crate) fn push(&mut self, frame: usize) {
+        if let Some(t) = self.current {
+            self.origins.insert(frame, t);
+        }
+    }
+
+    pub(crate) fn pop(&mut self, frame: usize) {
+        if let Some(t) = self.origins.get(&frame) {
+            self.current = Some(*t);
+        }
+    }
+
+    /// A value evaluated from the primitive `t`.
+    pub(crate) fn made(&mut self, value: usize, t: &Arc<Term>) {
+        let address = Arc::as_ptr(t) as usize;
+        self.counts.entry(address).or_insert_with(|| (t.clone(), 0));
+        self.values.insert(value, address);
+    }
+
+    /// A value built by the work of the current term.
+    pub(crate) fn built(&mut self, value: usize) {
+        if let Some(t) = self.current {
+            self.values.insert(value, t);
+        }
+    }
+
+    /// Applying `value` makes the term that made it current.
+    pub(crate) fn apply(&mut self, value: usize) {
+        if let Some(t) = self.values.get(&value) {
+            self.current = Some(*t);
+        }
+    }
+
+    pub(crate) fn credit(&mut self, steps: u64) {
+        self.total += steps;
+        if let Some(t) = self.current {
+            self.counts.get_mut(&t).unwrap().1 += steps;
+        }
+    }
+
+    /// Number of steps credited so far.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// The terms steps were credited to, hottest first.
+    pub fn hottest(&self) -> Vec<(&Arc<Term>, u64)> {
+        let mut hottest: Vec<_> = self.counts.values().map(|(t, n)| (t, *n)).collect();
+        hottest.sort_by_key(|(_, n)| Reverse(*n));
+        hottest
+    }
+
+    fn percent(&self, n: u64) -> f64 {
+        100.0 * n as f64 / self.total.max(1) as f64
+    }
+
+    /// The program's source with the steps spent on each line in the
+    /// margin, then the `top` hottest subterms.
+    pub fn report(&self, program: &Program, top: usize) -> String {
+        let mut hottest = self.hottest();
+        // ties in source order
+        hottest.sort_by_key(|(t, n)| (Reverse(*n), program.span(t).map(|span| span.start)));
+        let mut lines = vec![0; program.source.lines().count()];
+        let mut unplaced = 0;
+        for (t, n) in &hottest {
+            match program.span(t) {
+                Some(span) => lines[span.line - 1] += n,
+                None => unplaced += n,
+            }
+        }
+
+        let mut out = String::new();
+        writeln!(out, "Profile: {} steps", self.total).unwrap();
+        for (line, n) in program.source.lines().zip(&lines) {
+            if *n > 0 {
+                let percent = self.percent(*n);
+                writeln!(out, "{:>12} {:>6.2}% | {}", n, percent, line).unwrap();
+            } else {
+                writeln!(out, "{:>20} | {}", "", line).unwrap();
+            }
+        }
+        if unplaced > 0 {
+            writeln!(
+                out,
+                "{:>12} {:>6.2}% | (outside the source)",
+                unplaced,
+                self.percent(unplaced)
+            )
+            .unwrap();
+        }
+
+        writeln!(out, "\nHottest subterms:").unwrap();
+        writeln!(out, "{:>12} {:>7}  {:<9} term", "steps", "%", "at").unwrap();
+        for (t, n) in hottest.iter().take(top) {
+            let at = program
+                .span(t)
+                .map_or("?".to_string(), |span| span.to_string());
+            let text = truncate(&t.to_string(), 60);
+            writeln!(
+                out,
+                "{:>12} {:>6.2}%  {:<9} {}",
+                n,
+                self.percent(*n),
+                at,
+                text
+            )
+            .unwrap();
+        }
+        out
+    }
+}
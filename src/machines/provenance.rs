@@ -0,0 +1,68 @@
+use crate::term::{Program, Term};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+
+// Where each printed character came from
+
+/// A character the program printed.
+#[derive(Debug, Clone)]
+pub struct Printed {
+    pub c: char,
+    /// Steps taken before it was printed
+    pub step: u64,
+    /// The `.x` or `r` term that made the printing value
+    pub term: Option<Arc<Term>>,
+    /// Frames in the continuation when it was printed
+    pub depth: usize,
+}
+
+/// Every character the program printed, with where it came from.
+#[derive(Debug, Clone, Default)]
+pub struct Provenance {
+    // the term each printing value was made from, by value address. As
+    // printing values are only ever made from terms, a live one always has
+    // the latest entry for its address.
+    puts: HashMap<usize, Arc<Term>>,
+    printed: Vec<Printed>,
+}
+
+impl Provenance {
+    pub(crate) fn made(&mut self, value: usize, t: &Arc<Term>) {
+        self.puts.insert(value, t.clone());
+    }
+
+    pub(crate) fn print(&mut self, value: usize, c: char, step: u64, depth: usize) {
+        self.printed.push(Printed {
+            c,
+            step,
+            term: self.puts.get(&value).cloned(),
+            depth,
+        });
+    }
+
+    /// The characters printed so far, in order.
+    pub fn printed(&self) -> &[Printed] {
+        &self.printed
+    }
+
+    /// One line per character: its index in the output, the step that
+    /// printed it, the source position of its term, the depth, and the
+    /// character itself.
+    pub fn report(&self, program: &Program) -> String {
+        let mut out = String::from("index\tstep\tat\tdepth\tchar\n");
+        for (i, printed) in self.printed.iter().enumerate() {
+            let at = match printed.term.as_deref().and_then(|t| program.span(t)) {
+                Some(span) => span.to_string(),
+                None => "?".to_string(),
+            };
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{:?}",
+                i, printed.step, at, printed.depth, printed.c
+            )
+            .unwrap();
+        }
+        out
+    }
+}
@@ -0,0 +1,60 @@
+use crate::term::Term;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// Reading values back into terms that evaluate to them, the same way on
+// every machine
+
+/// What a value is made of, one level down.
+pub(crate) enum Shape<'a, V> {
+    /// A primitive, read back as itself
+    Atom(Term),
+    S1(&'a V),
+    S2(&'a V, &'a V),
+    K1(&'a V),
+    D1T(Arc<Term>),
+    D1V(&'a V),
+    /// A continuation, which no term evaluates to
+    C1,
+}
+
+/// Values a machine can read back.
+pub(crate) trait Readback: Sized {
+    fn shape(&self) -> Shape<'_, Self>;
+
+    /// Where the value is, so that one held in several places is read back
+    /// once.
+    fn address(&self) -> usize {
+        self as *const Self as usize
+    }
+}
+
+/// A term that evaluates to `v`, with no effects, or `None` if it holds a
+/// continuation. A `d` waiting on a value is read back as a promise of the
+/// term for that value, which does the same once forced. Shared values are
+/// read back once and the term shares them too.
+pub(crate) fn readback<V: Readback>(v: &V) -> Option<Arc<Term>> {
+    read(v, &mut HashMap::new())
+}
+
+// Terms already read back, by value address
+fn read<V: Readback>(v: &V, done: &mut HashMap<usize, Arc<Term>>) -> Option<Arc<Term>> {
+    if let Some(t) = done.get(&v.address()) {
+        return Some(t.clone());
+    }
+    let app = |t0: Term, t1: Arc<Term>| Arc::new(Term::App(Arc::new(t0), t1));
+    let t = match v.shape() {
+        Shape::Atom(t) => Arc::new(t),
+        Shape::S1(w) => app(Term::S, read(w, done)?),
+        Shape::S2(w0, w1) => {
+            let t0 = read(w0, done)?;
+            Arc::new(Term::App(app(Term::S, t0), read(w1, done)?))
+        }
+        Shape::K1(w) => app(Term::K, read(w, done)?),
+        Shape::D1T(t) => app(Term::D, t),
+        Shape::D1V(w) => app(Term::D, read(w, done)?),
+        Shape::C1 => return None,
+    };
+    done.insert(v.address(), t.clone());
+    Some(t)
+}
@@ -0,0 +1,273 @@
+use crate::term::Term;
+use std::collections::HashMap;
+
+// Values, terms and continuations shown within bounds, for people to read
+//
+// Parts nested deeper than the depth bound are shown as `<...>`, and text
+// past the width bound of a line as a trailing `...`. Only the outermost frames of a
+// long continuation are left out, as `<N frames>`, so the frames nearest
+// the hole stay in view. A node shown twice is labelled `#n=` where it is
+// first shown and written `#n#` after that, so shared parts are shown once
+// and the text stays linear in what is shown. Continuation frames are
+// labelled too, where the text of each before the hole starts, and a
+// continuation that reaches a frame already shown has that frame and the
+// ones below it written as its label.
+
+/// How much of a value, term or continuation to show; `None` for no bound.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    /// Deepest nesting shown
+    pub depth: Option<usize>,
+    /// Most characters shown, not counting labels
+    pub width: Option<usize>,
+    /// Most continuation frames shown
+    pub frames: Option<usize>,
+}
+
+impl Bounds {
+    /// Everything, with shared parts shown once.
+    pub const FULL: Bounds = Bounds {
+        depth: None,
+        width: None,
+        frames: None,
+    };
+
+    /// A line or two: what the stepper and error messages show.
+    pub const SHORT: Bounds = Bounds {
+        depth: Some(24),
+        width: Some(100),
+        frames: Some(16),
+    };
+
+    /// The same bounds, at most `width` characters wide.
+    pub fn narrow(self, width: usize) -> Bounds {
+        Bounds {
+            width: Some(self.width.map_or(width, |w| w.min(width))),
+            ..self
+        }
+    }
+
+    pub fn render(&self, x: &(impl Render + ?Sized)) -> String {
+        let mut renderer = Renderer::new(*self);
+        x.render(&mut renderer);
+        renderer.finish()
+    }
+}
+
+impl Default for Bounds {
+    fn default() -> Self {
+        Bounds::SHORT
+    }
+}
+
+/// Something that can be shown within bounds.
+pub trait Render {
+    fn render(&self, r: &mut Renderer);
+}
+
+// Where a label goes in the text
+enum Mark {
+    // where a node is first shown; labelled only if it is shown again
+    Def(usize),
+    Ref(usize),
+}
+
+/// Writes text within bounds, keeping track of shared nodes.
+pub struct Renderer {
+    bounds: Bounds,
+    out: String,
+    width: usize,
+    depth: usize,
+    full: bool,
+    // by byte offset in `out`, in order
+    marks: Vec<(usize, Mark)>,
+    // shared nodes seen, by address, and whether each was seen again
+    seen: HashMap<usize, usize>,
+    again: Vec<bool>,
+}
+
+impl Renderer {
+    fn new(bounds: Bounds) -> Self {
+        Renderer {
+            bounds,
+            out: String::new(),
+            width: 0,
+            depth: 0,
+            full: false,
+            marks: Vec::new(),
+            seen: HashMap::new(),
+            again: Vec::new(),
+        }
+    }
+
+    pub fn text(&mut self, s: &str) {
+        for c in s.chars() {
+            self.char(c);
+        }
+    }
+
+    pub fn char(&mut self, c: char) {
+        if self.room() {
+            self.out.push(c);
+            self.width += 1;
+        }
+    }
+
+    /// Starts a new line, with the width bound counting from there.
+    pub fn newline(&mut self) {
+        self.out.push('\n');
+        self.width = 0;
+        self.full = false;
+    }
+
+    // Whether there is room for more text on this line
+    fn room(&mut self) -> bool {
+        if !self.full && self.bounds.width.is_some_and(|max| self.width >= max) {
+            self.full = true;
+            self.out.push_str("...");
+        }
+        !self.full
+    }
+
+    /// Shows a part of what is being shown, one level deeper.
+    pub fn part(&mut self, x: &(impl Render + ?Sized)) {
+        if self.full {
+            return;
+        }
+        if self.bounds.depth.is_some_and(|max| self.depth >= max) {
+            self.text("<...>");
+            return;
+        }
+        self.depth += 1;
+        x.render(self);
+        self.depth -= 1;
+    }
+
+    /// Shows a part that other parts may share, told apart by address:
+    /// the first time in full, and after that by its label.
+    pub fn shared(&mut self, address: usize, x: &(impl Render + ?Sized)) {
+        if !self.room() {
+            return;
+        }
+        if self.again(address) {
+            return;
+        }
+        let node = self.node(address);
+        self.marks.push((self.out.len(), Mark::Def(node)));
+        self.part(x);
+    }
+
+    // Writes the label of a node already seen at this address, if any
+    fn again(&mut self, address: usize) -> bool {
+        let Some(&node) = self.seen.get(&address) else {
+            return false;
+        };
+        self.again[node] = true;
+        self.marks.push((self.out.len(), Mark::Ref(node)));
+        // counted as about as wide as its label
+        self.width += 3;
+        true
+    }
+
+    // A new node at this address
+    fn node(&mut self, address: usize) -> usize {
+        let node = self.again.len();
+        self.seen.insert(address, node);
+        self.again.push(false);
+        node
+    }
+
+    /// Shows a continuation from its frames, top first, each as the text
+    /// that goes before the hole and the text that goes after it. Frames
+    /// are told apart by address, and the first one already shown stands
+    /// for the rest of the continuation.
+    pub fn kont<'a, F: Frame + 'a>(&mut self, frames: impl Iterator<Item = &'a F>) {
+        if self.full {
+            return;
+        }
+        let mut shown: Vec<&F> = Vec::new();
+        let mut rest = 0;
+        let mut tail = None;
+        for frame in frames {
+            if self.seen.contains_key(&frame.address()) {
+                tail = Some(frame.address());
+                break;
+            }
+            match self.bounds.frames {
+                Some(max) if shown.len() >= max => rest += 1,
+                _ => shown.push(frame),
+            }
+        }
+        if let Some(address) = tail.filter(|_| self.room()) {
+            self.again(address);
+        }
+        if rest > 0 {
+            self.text(&format!("<{} frames>", rest));
+        }
+        // from the bottom up, so the values in them can refer to the
+        // frames below
+        let nodes: Vec<usize> = shown.iter().rev().map(|f| self.node(f.address())).collect();
+        for (frame, node) in shown.iter().rev().zip(nodes) {
+            if !self.room() {
+                // cut off, so shown in full wherever else it is
+                self.seen.remove(&frame.address());
+                continue;
+            }
+            self.marks.push((self.out.len(), Mark::Def(node)));
+            frame.before(self);
+        }
+        self.text("()");
+        for frame in &shown {
+            frame.after(self);
+        }
+    }
+
+    fn finish(self) -> String {
+        let mut labels = vec![0; self.again.len()];
+        let mut next = 0;
+        for (label, again) in labels.iter_mut().zip(&self.again) {
+            if *again {
+                next += 1;
+                *label = next;
+            }
+        }
+        let mut out = String::with_capacity(self.out.len() + 4 * self.marks.len());
+        let mut pos = 0;
+        for (at, mark) in &self.marks {
+            out.push_str(&self.out[pos..*at]);
+            pos = *at;
+            match *mark {
+                Mark::Def(node) if self.again[node] => out.push_str(&format!("#{}=", labels[node])),
+                Mark::Def(_) => {}
+                Mark::Ref(node) => out.push_str(&format!("#{}#", labels[node])),
+            }
+        }
+        out.push_str(&self.out[pos..]);
+        out
+    }
+}
+
+/// A continuation frame, shown around the hole of the frames above it.
+pub trait Frame {
+    fn before(&self, r: &mut Renderer);
+    fn after(&self, r: &mut Renderer);
+
+    /// Where the frame is, the same for every continuation sharing it.
+    fn address(&self) -> usize {
+        self as *const Self as *const () as usize
+    }
+}
+
+impl Render for Term {
+    fn render(&self, r: &mut Renderer) {
+        match self {
+            Term::App(t0, t1) => {
+                r.char('`');
+                r.part(t0.as_ref());
+                r.part(t1.as_ref());
+            }
+            // atoms print the same either way
+            t => r.text(&t.to_string()),
+        }
+    }
+}
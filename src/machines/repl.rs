@@ -0,0 +1,206 @@
+use super::interrupt::{self, Interrupts};
+use super::output;
+use super::{anaive, arc, shown, truncate, Options, Outcome};
+use crate::term::{parse_entry, Entry, Term};
+use pest::error::{ErrorVariant, InputLocation};
+use rustyline::error::ReadlineError;
+use std::collections::HashMap;
+use std::mem::take;
+use std::sync::Arc;
+
+// A read-eval-print loop, with names for terms
+
+const HELP: &str = "\
+Type a term to evaluate it, or NAME = TERM to give a term a name that later
+terms can use as $NAME. A term left unfinished goes on to the next line,
+and commands typed in the middle of it leave it to be finished after them.
+Definitions are terms, not values: their effects happen each time they are
+used. Ctrl-C stops an evaluation.
+  :defs          list definitions
+  :help          show this
+  :quit          leave (or Ctrl-D)";
+
+/// Which machine evaluates what is typed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Machine {
+    Anaive,
+    Arc,
+}
+
+pub struct Repl {
+    machine: Machine,
+    options: Options,
+    defs: HashMap<String, Arc<Term>>,
+    interrupts: Interrupts,
+    // the lines of an entry typed so far
+    pending: String,
+}
+
+impl Repl {
+    pub fn new(machine: Machine, options: &Options) -> std::io::Result<Self> {
+        Ok(Repl {
+            machine,
+            options: options.clone(),
+            defs: HashMap::new(),
+            interrupts: Interrupts::install()?,
+            pending: String::new(),
+        })
+    }
+
+    /// Reads an entry, which may take several lines, or a command, which
+    /// may come in the middle of an entry that goes on after it. `None` at
+    /// the end of input, once an entry left unfinished there is given back
+    /// to be reported.
+    fn read(&mut self, editor: &mut rustyline::DefaultEditor) -> Option<String> {
+        loop {
+            let prompt = if self.pending.is_empty() {
+                "unl> "
+            } else {
+                "...> "
+            };
+            match editor.readline(prompt) {
+                Ok(line) if line.trim().starts_with(':') => return Some(line),
+                Ok(line) => {
+                    self.pending.push_str(&line);
+                    self.pending.push('\n');
+                }
+                // Ctrl-C drops what was typed so far
+                Err(ReadlineError::Interrupted) => self.pending.clear(),
+                Err(_) if self.pending.trim().is_empty() => return None,
+                Err(_) => {
+                    eprintln!("Input ended in the middle of an entry");
+                    return Some(take(&mut self.pending));
+                }
+            }
+            let input = &self.pending;
+            if input.trim().is_empty() && !input.is_empty() {
+                return Some(take(&mut self.pending));
+            }
+            match parse_entry(input, &self.defs) {
+                Err(e) if unfinished(&e, input) => {}
+                _ => return Some(take(&mut self.pending)),
+            }
+        }
+    }
+
+    /// Runs an entry: defines a name, or evaluates a term and shows its
+    /// value apart from what it printed.
+    pub fn eval(&mut self, input: &str) {
+        match parse_entry(input, &self.defs) {
+            Ok(Entry::Define(name, t)) => {
+                self.defs.insert(name, t);
+            }
+            Ok(Entry::Eval(t)) => match self.machine {
+                Machine::Anaive => self.eval_anaive(t),
+                Machine::Arc => self.eval_arc(t),
+            },
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    fn eval_anaive(&self, t: Arc<Term>) {
+        let mut state = anaive::new(t.as_ref().clone());
+        let (result, stats) = loop {
+            match state.finish_polling(interrupt::POLL, |_| !self.interrupts.pending()) {
+                Outcome::Finished(result) => break result,
+                Outcome::Suspended(s) | Outcome::Exceeded(_, s) => state = s,
+            }
+            if self.interrupts.take_stop() {
+                output::flush();
+                if state.stats().output > 0 {
+                    println!();
+                }
+                eprintln!("Interrupted {}", state.summary());
+                return;
+            }
+            if self.interrupts.take_report() {
+                eprintln!("\n{}", state.summary());
+            }
+        };
+        output::flush();
+        // what the program printed ends its own line
+        if stats.output > 0 {
+            println!();
+        }
+        println!(
+            "= {}",
+            shown(&result, &self.options, anaive::Value::readback)
+        );
+    }
+
+    fn eval_arc(&self, t: Arc<Term>) {
+        // as in arc::main, only a counted machine can keep to a value limit
+        if self.options.limits.values.is_some() {
+            self.eval_arc_on::<arc::Counted<arc::Local>>(t)
+        } else {
+            self.eval_arc_on::<arc::Local>(t)
+        }
+    }
+
+    fn eval_arc_on<P: arc::Share>(&self, t: Arc<Term>) {
+        let mut state: arc::State<P> = arc::new(t)
+            .simplify(self.options.simplify)
+            .limits(self.options.limits);
+        let result = loop {
+            if let Some(result) = state.run_polling(interrupt::POLL, |_| !self.interrupts.pending())
+            {
+                break Some(result);
+            }
+            if self.interrupts.take_stop() {
+                break None;
+            }
+            if self.interrupts.take_report() {
+                eprintln!("\n{}", state.summary());
+            }
+        };
+        output::flush();
+        if state.output() > 0 {
+            println!();
+        }
+        match result {
+            Some(Ok(result)) => println!(
+                "= {}",
+                shown(result.as_ref(), &self.options, arc::Value::readback)
+            ),
+            Some(Err(limit)) => eprintln!("Stopped: {}\n{}", limit, state.summary()),
+            None => eprintln!("Interrupted {}", state.summary()),
+        }
+    }
+
+    fn defs(&self) {
+        let mut defs: Vec<_> = self.defs.iter().collect();
+        defs.sort_by_key(|(name, _)| *name);
+        for (name, t) in defs {
+            println!("{} = {}", name, truncate(&t.to_string(), 70));
+        }
+    }
+}
+
+// Whether parsing failed only for want of more input
+fn unfinished(e: &pest::error::Error<crate::term::Rule>, input: &str) -> bool {
+    matches!(e.variant, ErrorVariant::ParsingError { .. })
+        && matches!(e.location, InputLocation::Pos(pos) if pos >= input.trim_end().len())
+}
+
+pub fn main(machine: Machine, options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    let mut repl = Repl::new(machine, options)?;
+    let mut editor = rustyline::DefaultEditor::new()?;
+    println!("Type :help for help.");
+    while let Some(input) = repl.read(&mut editor) {
+        let entry = input.trim();
+        if entry.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(entry)?;
+        match entry {
+            ":help" | ":h" => println!("{}", HELP),
+            ":defs" | ":d" => repl.defs(),
+            ":quit" | ":q" => break,
+            _ if entry.starts_with(':') => {
+                println!("Unknown command `{}`; type :help for help", entry)
+            }
+            _ => repl.eval(&input),
+        }
+    }
+    Ok(())
+}
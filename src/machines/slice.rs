@@ -0,0 +1,410 @@
+use super::readback::{readback, Readback, Shape};
+use super::{output, print_result, Options};
+use crate::term::{Program, Term};
+use std::collections::HashMap;
+use std::fmt::{Display, Write as _};
+use std::mem::take;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::Arc;
+
+// A machine that keeps track of which parts of the program each value
+// depends on, for slicing. Much slower than the others, and its memory
+// grows with the dependencies that are kept alive.
+
+/// What a value depends on: source terms, joined together.
+pub struct Dep {
+    term: Option<Arc<Term>>,
+    parents: Vec<Deps>,
+}
+
+pub type Deps = Rc<Dep>;
+
+fn leaf(t: &Arc<Term>) -> Deps {
+    Rc::new(Dep {
+        term: Some(t.clone()),
+        parents: Vec::new(),
+    })
+}
+
+fn join(deps: &[&Deps]) -> Deps {
+    let mut parents: Vec<Deps> = Vec::with_capacity(deps.len());
+    for dep in deps {
+        if !parents.iter().any(|p| Rc::ptr_eq(p, dep)) {
+            parents.push(Rc::clone(dep));
+        }
+    }
+    if parents.len() == 1 {
+        return parents.pop().unwrap();
+    }
+    Rc::new(Dep {
+        term: None,
+        parents,
+    })
+}
+
+impl Drop for Dep {
+    // chains of dependencies get as long as the run, so dropping them
+    // recursively would overflow the stack
+    fn drop(&mut self) {
+        let mut todo = take(&mut self.parents);
+        while let Some(dep) = todo.pop() {
+            if let Ok(mut dep) = Rc::try_unwrap(dep) {
+                todo.append(&mut dep.parents);
+            }
+        }
+    }
+}
+
+/// A value along with what it depends on.
+#[derive(Clone)]
+pub struct Tagged {
+    pub value: Rc<Value>,
+    pub deps: Deps,
+}
+
+impl Tagged {
+    fn new(value: Value, deps: Deps) -> Self {
+        Tagged {
+            value: Rc::new(value),
+            deps,
+        }
+    }
+
+    /// A term that evaluates to this value, or `None` if it holds a
+    /// continuation, as the other machines read values back.
+    pub fn readback(&self) -> Option<Arc<Term>> {
+        readback(self)
+    }
+
+    // The same value, depending on more
+    fn and(&self, deps: &Deps) -> Self {
+        Tagged {
+            value: self.value.clone(),
+            deps: join(&[&self.deps, deps]),
+        }
+    }
+}
+
+pub enum Value {
+    I0,
+    S0,
+    K0,
+    V0,
+    D0,
+    C0,
+    Put0(char),
+    S1(Tagged),
+    S2(Tagged, Tagged),
+    K1(Tagged),
+    D1T(Arc<Term>),
+    D1V(Tagged),
+    C1(Option<Rc<Kont>>),
+}
+
+impl Readback for Tagged {
+    fn shape(&self) -> Shape<'_, Self> {
+        match self.value.as_ref() {
+            Value::I0 => Shape::Atom(Term::I),
+            Value::S0 => Shape::Atom(Term::S),
+            Value::K0 => Shape::Atom(Term::K),
+            Value::V0 => Shape::Atom(Term::V),
+            Value::D0 => Shape::Atom(Term::D),
+            Value::C0 => Shape::Atom(Term::C),
+            Value::Put0(c) => Shape::Atom(Term::Put(*c)),
+            Value::S1(w) => Shape::S1(w),
+            Value::S2(w0, w1) => Shape::S2(w0, w1),
+            Value::K1(w) => Shape::K1(w),
+            Value::D1T(t) => Shape::D1T(t.clone()),
+            Value::D1V(w) => Shape::D1V(w),
+            Value::C1(_) => Shape::C1,
+        }
+    }
+
+    // the same value may be tagged in several places
+    fn address(&self) -> usize {
+        Rc::as_ptr(&self.value) as usize
+    }
+}
+
+impl Display for Tagged {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.value.as_ref() {
+            Value::I0 => write!(f, "i"),
+            Value::S0 => write!(f, "s"),
+            Value::K0 => write!(f, "k"),
+            Value::V0 => write!(f, "v"),
+            Value::D0 => write!(f, "d"),
+            Value::C0 => write!(f, "c"),
+            Value::Put0('\n') => write!(f, "r"),
+            Value::Put0(c) => write!(f, ".{}", c),
+            Value::S1(w) => write!(f, "`s{}", w),
+            Value::S2(w0, w1) => write!(f, "``s{}{}", w0, w1),
+            Value::K1(w) => write!(f, "`k{}", w),
+            Value::D1T(t) => write!(f, "`d[{}]", t),
+            Value::D1V(w) => write!(f, "`d{}", w),
+            Value::C1(_) => write!(f, "`c(...)"),
+        }
+    }
+}
+
+// Each frame also keeps the dependencies of the application it belongs to
+pub enum Kont {
+    BindT(Arc<Term>, Deps, Option<Rc<Kont>>),
+    BindV(Tagged, Deps, Option<Rc<Kont>>),
+    BindW(Tagged, Deps, Option<Rc<Kont>>),
+    SWait(Tagged, Tagged, Deps, Option<Rc<Kont>>),
+}
+
+enum Config {
+    Eval(Arc<Term>, Option<Rc<Kont>>),
+    ApplyT(Tagged, Arc<Term>, Deps, Option<Rc<Kont>>),
+    ApplyV(Tagged, Tagged, Deps, Option<Rc<Kont>>),
+    ApplyK(Option<Rc<Kont>>, Tagged),
+}
+
+fn atom(t: &Term) -> Value {
+    match t {
+        Term::I => Value::I0,
+        Term::S => Value::S0,
+        Term::K => Value::K0,
+        Term::V => Value::V0,
+        Term::D => Value::D0,
+        Term::C => Value::C0,
+        Term::R => Value::Put0('\n'),
+        Term::Put(c) => Value::Put0(*c),
+        Term::App(..) => unreachable!(),
+    }
+}
+
+fn eval(t: Arc<Term>, k: Option<Rc<Kont>>) -> Config {
+    match t.as_ref() {
+        Term::App(t0, t1) => {
+            let app = leaf(&t);
+            Config::Eval(t0.clone(), Some(Rc::new(Kont::BindT(t1.clone(), app, k))))
+        }
+        _ => Config::ApplyK(k, Tagged::new(atom(&t), leaf(&t))),
+    }
+}
+
+fn apply_t(v: Tagged, t: Arc<Term>, app: Deps, k: Option<Rc<Kont>>) -> Config {
+    match v.value.as_ref() {
+        Value::D0 => Config::ApplyK(k, Tagged::new(Value::D1T(t), join(&[&v.deps, &app]))),
+        _ => Config::Eval(t, Some(Rc::new(Kont::BindV(v, app, k)))),
+    }
+}
+
+// What an application results in depends on the operator and on the
+// application itself, and on the argument only where the argument is kept.
+fn apply_v(state: &mut State, v: Tagged, w: Tagged, app: Deps, k: Option<Rc<Kont>>) -> Config {
+    let op = join(&[&v.deps, &app]);
+    match v.value.as_ref() {
+        Value::I0 => Config::ApplyK(k, w.and(&op)),
+        Value::Put0(c) => {
+            output::put(*c);
+            state.printed.push(op.clone());
+            Config::ApplyK(k, w.and(&op))
+        }
+        Value::K0 => Config::ApplyK(k, Tagged::new(Value::K1(w), op)),
+        // the argument is dropped, and so are its dependencies
+        Value::K1(x) => Config::ApplyK(k, x.and(&op)),
+        Value::V0 => Config::ApplyK(k, Tagged::new(Value::V0, op)),
+        Value::C0 => {
+            let c1 = Tagged::new(Value::C1(k.clone()), op);
+            Config::ApplyV(w, c1, app, k)
+        }
+        Value::C1(k1) => Config::ApplyK(k1.clone(), w.and(&op)),
+        Value::D0 => Config::ApplyK(k, Tagged::new(Value::D1V(w), op)),
+        Value::D1T(t0) => Config::Eval(t0.clone(), Some(Rc::new(Kont::BindW(w, op, k)))),
+        Value::D1V(x) => Config::ApplyV(x.and(&op), w, app, k),
+        Value::S0 => Config::ApplyK(k, Tagged::new(Value::S1(w), op)),
+        Value::S1(x) => Config::ApplyK(k, Tagged::new(Value::S2(x.clone(), w), op)),
+        // the argument is shared between both applications
+        Value::S2(x, y) => {
+            let wait = Kont::SWait(y.and(&op), w.clone(), app.clone(), k);
+            Config::ApplyV(x.and(&op), w, app, Some(Rc::new(wait)))
+        }
+    }
+}
+
+fn apply_k(k: &Kont, w: Tagged) -> Config {
+    match k {
+        Kont::BindT(t, app, k) => Config::ApplyT(w, t.clone(), app.clone(), k.clone()),
+        Kont::BindV(v, app, k) => Config::ApplyV(v.clone(), w, app.clone(), k.clone()),
+        // a forced promise is applied as the promise
+        Kont::BindW(w1, promise, k) => {
+            Config::ApplyV(w.and(promise), w1.clone(), promise.clone(), k.clone())
+        }
+        Kont::SWait(v1, v, app, k) => {
+            let bind = Kont::BindV(w, app.clone(), k.clone());
+            Config::ApplyV(v1.clone(), v.clone(), app.clone(), Some(Rc::new(bind)))
+        }
+    }
+}
+
+pub struct State {
+    config: Option<Config>,
+    steps: u64,
+    printed: Vec<Deps>,
+}
+
+pub fn new(t: impl Into<Arc<Term>>) -> State {
+    State {
+        config: Some(Config::Eval(t.into(), None)),
+        steps: 0,
+        printed: Vec::new(),
+    }
+}
+
+impl State {
+    /// Takes a transition, or gives the result if the program is done.
+    pub fn step(&mut self) -> Option<Tagged> {
+        let config = match self.config.take().unwrap() {
+            Config::Eval(t, k) => eval(t, k),
+            Config::ApplyT(v, t, app, k) => apply_t(v, t, app, k),
+            Config::ApplyV(v, w, app, k) => apply_v(self, v, w, app, k),
+            Config::ApplyK(Some(k), w) => apply_k(&k, w),
+            Config::ApplyK(None, v) => {
+                self.config = Some(Config::ApplyK(None, v.clone()));
+                return Some(v);
+            }
+        };
+        self.steps += 1;
+        self.config = Some(config);
+        None
+    }
+
+    /// Number of transitions taken so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// What each character printed so far depends on, in order.
+    pub fn printed(&self) -> &[Deps] {
+        &self.printed
+    }
+
+    pub fn run(&mut self) -> Tagged {
+        loop {
+            if let Some(v) = self.step() {
+                return v;
+            }
+        }
+    }
+}
+
+/// The source terms some dependencies come down to.
+#[derive(Default)]
+pub struct Slice {
+    // by term address
+    terms: HashMap<usize, Arc<Term>>,
+}
+
+pub fn slice(deps: &Deps) -> Slice {
+    let mut slice = Slice::default();
+    let mut seen = HashMap::new();
+    let mut todo = vec![deps];
+    while let Some(dep) = todo.pop() {
+        if seen.insert(Rc::as_ptr(dep), ()).is_some() {
+            continue;
+        }
+        if let Some(t) = &dep.term {
+            slice.terms.insert(Arc::as_ptr(t) as usize, t.clone());
+        }
+        todo.extend(&dep.parents);
+    }
+    slice
+}
+
+impl Slice {
+    pub fn contains(&self, t: &Term) -> bool {
+        self.terms.contains_key(&(t as *const Term as usize))
+    }
+
+    /// Number of terms in the slice.
+    pub fn len(&self) -> usize {
+        self.terms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// The source with `^` under the terms in the slice. An application is
+    /// marked at its backquote, as its parts are marked on their own.
+    pub fn report(&self, program: &Program) -> String {
+        let source = &program.source;
+        let mut marks = vec![false; source.len()];
+        for t in self.terms.values() {
+            if let Some(span) = program.span(t) {
+                let end = match t.as_ref() {
+                    Term::App(..) => span.start + 1,
+                    _ => span.end,
+                };
+                marks[span.start..end].fill(true);
+            }
+        }
+
+        let mut out = String::new();
+        writeln!(out, "Slice: {} terms", self.len()).unwrap();
+        let mut start = 0;
+        for line in source.split_inclusive('\n') {
+            let text = line.trim_end_matches(['\n', '\r']);
+            writeln!(out, "{}", text).unwrap();
+            let under: String = text
+                .char_indices()
+                .map(|(i, c)| match (marks[start + i], c) {
+                    (true, _) => '^',
+                    (false, '\t') => '\t',
+                    _ => ' ',
+                })
+                .collect();
+            if under.contains('^') {
+                writeln!(out, "{}", under.trim_end()).unwrap();
+            }
+            start += line.len();
+        }
+        out
+    }
+}
+
+/// What to slice for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    /// The final value
+    Result,
+    /// The character at this position in the output, from 0
+    Output(usize),
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "result" => Ok(Target::Result),
+            _ => s
+                .parse()
+                .map(Target::Output)
+                .map_err(|_| format!("expected `result` or an output position, got `{}`", s)),
+        }
+    }
+}
+
+pub fn main(program: &Program, target: Target, options: &Options) {
+    let mut state = new(program.term.clone());
+    let result = state.run();
+    output::flush();
+    print_result(&result, options, Tagged::readback);
+    let deps = match target {
+        Target::Result => Some(&result.deps),
+        Target::Output(i) => state.printed().get(i),
+    };
+    match deps {
+        Some(deps) => eprint!("{}", slice(deps).report(program)),
+        None => eprintln!(
+            "The program printed only {} characters",
+            state.printed().len()
+        ),
+    }
+}
@@ -0,0 +1,561 @@
+use crate::term::{parse_program, Program, Term};
+use std::collections::HashMap;
+use std::fmt::{Display, Write as _};
+use std::path::Path;
+use std::sync::Arc;
+
+// Machine states written down, to be taken up again later or on another
+// machine. Every machine has the same terms, values and frames, so a
+// snapshot is a graph of those, each shared node written once.
+
+/// Position of a node in `Snapshot::nodes`.
+pub type Id = usize;
+
+/// A value, with the nodes it holds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    I0,
+    S0,
+    K0,
+    V0,
+    D0,
+    C0,
+    Put0(char),
+    S1(Id),
+    S2(Id, Id),
+    K1(Id),
+    D1T(Id),
+    D1V(Id),
+    C1(Option<Id>),
+}
+
+/// A continuation frame, with the frame below it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kont {
+    BindT(Id, Option<Id>),
+    BindV(Id, Option<Id>),
+    BindW(Id, Option<Id>),
+    SWait(Id, Id, Option<Id>),
+}
+
+/// What the machine was doing, as in the unfused machine.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Config {
+    Eval(Id, Option<Id>),
+    ApplyT(Id, Id, Option<Id>),
+    ApplyV(Id, Id, Option<Id>),
+    ApplyK(Option<Id>, Id),
+}
+
+/// A node of a snapshot. Nodes only hold nodes that come before them.
+#[derive(Debug, Clone)]
+pub enum Node {
+    /// A term with no parts; never an application
+    Atom(Term),
+    App(Id, Id),
+    /// The term of the program that starts at this byte of its source
+    At(usize),
+    Value(Value),
+    Kont(Kont),
+}
+
+/// A machine state, written so that any machine can take it up.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    /// The source of the program, if its terms are written as positions
+    pub source: Option<String>,
+    pub nodes: Vec<Node>,
+    pub config: Config,
+    /// Machine rules applied so far
+    pub steps: u64,
+}
+
+/// What is wrong with a snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error(pub String);
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "bad snapshot: {}", self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+const HEADER: &str = "unabs snapshot 1";
+
+/// Puts a snapshot together from a machine's state, writing each node once
+/// however many times it is shared. Nodes are told apart by address, so the
+/// state must stay alive and unchanged while it is built.
+pub(crate) struct Builder<'a> {
+    program: Option<&'a Program>,
+    nodes: Vec<Node>,
+    terms: HashMap<usize, Id>,
+    // values and frames
+    shared: HashMap<usize, Id>,
+}
+
+impl<'a> Builder<'a> {
+    pub(crate) fn new(program: Option<&'a Program>) -> Self {
+        Builder {
+            program,
+            nodes: Vec::new(),
+            terms: HashMap::new(),
+            shared: HashMap::new(),
+        }
+    }
+
+    /// The node for a term, adding it and its parts if need be. Terms of the
+    /// program are written as their positions in it.
+    pub(crate) fn term(&mut self, t: &Term) -> Id {
+        let address = |t: &Term| t as *const Term as usize;
+        let mut todo = vec![(t, false)];
+        while let Some((t, ready)) = todo.pop() {
+            if self.terms.contains_key(&address(t)) {
+                continue;
+            }
+            let node = match (self.program.and_then(|p| p.span(t)), t) {
+                (Some(span), _) => Node::At(span.start),
+                (None, Term::App(t0, t1)) if !ready => {
+                    todo.push((t, true));
+                    todo.push((t1, false));
+                    todo.push((t0, false));
+                    continue;
+                }
+                (None, Term::App(t0, t1)) => {
+                    Node::App(self.terms[&address(t0)], self.terms[&address(t1)])
+                }
+                (None, _) => Node::Atom(t.clone()),
+            };
+            self.nodes.push(node);
+            self.terms.insert(address(t), self.nodes.len() - 1);
+        }
+        self.terms[&address(t)]
+    }
+
+    /// The node already added for the value or frame at this address.
+    pub(crate) fn get(&self, address: usize) -> Option<Id> {
+        self.shared.get(&address).copied()
+    }
+
+    /// Adds the node for the value or frame at this address.
+    pub(crate) fn add(&mut self, address: usize, node: Node) -> Id {
+        self.nodes.push(node);
+        self.shared.insert(address, self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    pub(crate) fn finish(self, config: Config, steps: u64) -> Snapshot {
+        // the source is only needed to look positions up in
+        let located = self.nodes.iter().any(|node| matches!(node, Node::At(_)));
+        Snapshot {
+            source: self.program.filter(|_| located).map(|p| p.source.clone()),
+            nodes: self.nodes,
+            config,
+            steps,
+        }
+    }
+}
+
+impl Snapshot {
+    /// Checks that every node holds nodes of the right kind that come
+    /// before it, so that machines can take the snapshot up as it is.
+    pub fn check(&self) -> Result<(), Error> {
+        let nodes = &self.nodes;
+        let is_term = |id: Id| matches!(nodes[id], Node::Atom(_) | Node::App(..) | Node::At(_));
+        let is_value = |id: Id| matches!(nodes[id], Node::Value(_));
+        let is_kont = |id: Id| matches!(nodes[id], Node::Kont(_));
+        let check = |at: Id, id: Id, ok: &dyn Fn(Id) -> bool, what: &str| {
+            if id < at && ok(id) {
+                Ok(())
+            } else {
+                Err(Error(format!(
+                    "node {} should refer to an earlier {}, not {}",
+                    at, what, id
+                )))
+            }
+        };
+        let check_kont =
+            |at: Id, k: Option<Id>| k.map_or(Ok(()), |k| check(at, k, &is_kont, "frame"));
+        for (at, node) in nodes.iter().enumerate() {
+            match *node {
+                Node::Atom(Term::App(..)) => {
+                    return Err(Error(format!(
+                        "node {} is an application without parts",
+                        at
+                    )))
+                }
+                Node::Atom(_) => {}
+                Node::At(_) if self.source.is_none() => {
+                    return Err(Error(format!(
+                        "node {} is a position, but there is no source",
+                        at
+                    )))
+                }
+                Node::At(_) => {}
+                Node::App(t0, t1) => {
+                    check(at, t0, &is_term, "term")?;
+                    check(at, t1, &is_term, "term")?;
+                }
+                Node::Value(v) => match v {
+                    Value::S1(x) | Value::K1(x) | Value::D1V(x) => {
+                        check(at, x, &is_value, "value")?
+                    }
+                    Value::S2(x, y) => {
+                        check(at, x, &is_value, "value")?;
+                        check(at, y, &is_value, "value")?;
+                    }
+                    Value::D1T(t) => check(at, t, &is_term, "term")?,
+                    Value::C1(k) => check_kont(at, k)?,
+                    _ => {}
+                },
+                Node::Kont(k) => match k {
+                    Kont::BindT(t, k) => {
+                        check(at, t, &is_term, "term")?;
+                        check_kont(at, k)?;
+                    }
+                    Kont::BindV(v, k) | Kont::BindW(v, k) => {
+                        check(at, v, &is_value, "value")?;
+                        check_kont(at, k)?;
+                    }
+                    Kont::SWait(v1, v, k) => {
+                        check(at, v1, &is_value, "value")?;
+                        check(at, v, &is_value, "value")?;
+                        check_kont(at, k)?;
+                    }
+                },
+            }
+        }
+        let end = nodes.len();
+        match self.config {
+            Config::Eval(t, k) => {
+                check(end, t, &is_term, "term")?;
+                check_kont(end, k)
+            }
+            Config::ApplyT(v, t, k) => {
+                check(end, v, &is_value, "value")?;
+                check(end, t, &is_term, "term")?;
+                check_kont(end, k)
+            }
+            Config::ApplyV(v, w, k) => {
+                check(end, v, &is_value, "value")?;
+                check(end, w, &is_value, "value")?;
+                check_kont(end, k)
+            }
+            Config::ApplyK(k, v) => {
+                check_kont(end, k)?;
+                check(end, v, &is_value, "value")
+            }
+        }
+    }
+
+    /// The program the snapshot was taken from, if it was written with it.
+    /// Every position in the snapshot is checked to be a term of it.
+    pub fn program(&self) -> Result<Option<Program>, Error> {
+        let Some(source) = &self.source else {
+            return Ok(None);
+        };
+        let program = parse_program(source).map_err(|e| Error(format!("in source: {}", e)))?;
+        self.terms(Some(&program))?;
+        Ok(Some(program))
+    }
+
+    /// The term of each node that is one, after checking the snapshot.
+    /// Positions are looked up in `program`, which should be the one the
+    /// snapshot was taken from.
+    pub(crate) fn terms(&self, program: Option<&Program>) -> Result<Vec<Option<Arc<Term>>>, Error> {
+        self.check()?;
+        let located = program.map(located).unwrap_or_default();
+        let mut terms: Vec<Option<Arc<Term>>> = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            let t = match node {
+                Node::Atom(t) => Some(Arc::new(t.clone())),
+                Node::App(t0, t1) => Some(Arc::new(Term::App(
+                    terms[*t0].clone().unwrap(),
+                    terms[*t1].clone().unwrap(),
+                ))),
+                Node::At(start) => match located.get(start) {
+                    Some(t) => Some(t.clone()),
+                    None => {
+                        return Err(Error(format!("no term of the program starts at {}", start)))
+                    }
+                },
+                Node::Value(_) | Node::Kont(_) => None,
+            };
+            terms.push(t);
+        }
+        Ok(terms)
+    }
+
+    /// The snapshot as text: a header, the step count, the source if any,
+    /// then one line per node, numbered, and the configuration.
+    pub fn text(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "{}\nsteps {}", HEADER, self.steps).unwrap();
+        if let Some(source) = &self.source {
+            writeln!(out, "source {}\n{}", source.len(), source).unwrap();
+        }
+        let k = |k: Option<Id>| k.map_or("-".to_string(), |k| k.to_string());
+        for (id, node) in self.nodes.iter().enumerate() {
+            let text = match node {
+                Node::Atom(Term::Put(c)) => format!("put {}", *c as u32),
+                Node::Atom(t) => t.to_string(),
+                Node::App(t0, t1) => format!("app {} {}", t0, t1),
+                Node::At(start) => format!("at {}", start),
+                Node::Value(v) => match *v {
+                    Value::Put0(c) => format!("Put0 {}", c as u32),
+                    Value::S1(x) => format!("S1 {}", x),
+                    Value::S2(x, y) => format!("S2 {} {}", x, y),
+                    Value::K1(x) => format!("K1 {}", x),
+                    Value::D1T(t) => format!("D1T {}", t),
+                    Value::D1V(x) => format!("D1V {}", x),
+                    Value::C1(k1) => format!("C1 {}", k(k1)),
+                    v => format!("{:?}", v),
+                },
+                Node::Kont(frame) => match *frame {
+                    Kont::BindT(t, k1) => format!("BindT {} {}", t, k(k1)),
+                    Kont::BindV(v, k1) => format!("BindV {} {}", v, k(k1)),
+                    Kont::BindW(w, k1) => format!("BindW {} {}", w, k(k1)),
+                    Kont::SWait(v1, v, k1) => format!("SWait {} {} {}", v1, v, k(k1)),
+                },
+            };
+            writeln!(out, "{} {}", id, text).unwrap();
+        }
+        let config = match self.config {
+            Config::Eval(t, k1) => format!("Eval {} {}", t, k(k1)),
+            Config::ApplyT(v, t, k1) => format!("ApplyT {} {} {}", v, t, k(k1)),
+            Config::ApplyV(v, w, k1) => format!("ApplyV {} {} {}", v, w, k(k1)),
+            Config::ApplyK(k1, v) => format!("ApplyK {} {}", k(k1), v),
+        };
+        writeln!(out, "state {}", config).unwrap();
+        out
+    }
+
+    /// Reads a snapshot written by `text`, and checks it.
+    pub fn read(text: &str) -> Result<Self, Error> {
+        let mut rest = text;
+        let mut number = 0;
+        let (_, header) = next_line(&mut rest, &mut number)?;
+        if header != HEADER {
+            return Err(Error(format!("expected `{}`, not `{}`", HEADER, header)));
+        }
+        let (at, steps) = next_line(&mut rest, &mut number)?;
+        let steps = match steps.split_once(' ') {
+            Some(("steps", n)) => n.parse().map_err(|_| bad(at, "a step count"))?,
+            _ => return Err(bad(at, "`steps N`")),
+        };
+
+        let mut source = None;
+        let mut nodes = Vec::new();
+        loop {
+            let (at, line) = next_line(&mut rest, &mut number)?;
+            let words: Vec<&str> = line.split(' ').collect();
+            match words.as_slice() {
+                ["source", len] if source.is_none() && nodes.is_empty() => {
+                    let len: usize = len.parse().map_err(|_| bad(at, "a length"))?;
+                    let text = rest
+                        .get(..len)
+                        .filter(|_| rest[len..].starts_with('\n'))
+                        .ok_or_else(|| bad(at, "that many bytes of source and a newline"))?;
+                    source = Some(text.to_string());
+                    number += text.matches('\n').count() + 1;
+                    rest = &rest[len + 1..];
+                }
+                ["state", config @ ..] => {
+                    let config = parse_config(config).ok_or_else(|| bad(at, "a configuration"))?;
+                    let snapshot = Snapshot {
+                        source,
+                        nodes,
+                        config,
+                        steps,
+                    };
+                    snapshot.check()?;
+                    return Ok(snapshot);
+                }
+                [id, node @ ..] if id.parse() == Ok(nodes.len()) => {
+                    nodes.push(parse_node(node).ok_or_else(|| bad(at, "a node"))?);
+                }
+                _ => return Err(bad(at, &format!("node {} or the state", nodes.len()))),
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, self.text())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self::read(&std::fs::read_to_string(path)?)?)
+    }
+}
+
+/// Saves a snapshot, saying where or why not.
+pub fn save(snapshot: &Snapshot, path: &Path) {
+    match snapshot.save(path) {
+        Ok(()) => eprintln!("Saved to {}", path.display()),
+        Err(e) => eprintln!("Could not save to {}: {}", path.display(), e),
+    }
+}
+
+// The next line of the text, with its number
+fn next_line<'a>(rest: &mut &'a str, number: &mut usize) -> Result<(usize, &'a str), Error> {
+    let (line, after) = rest
+        .split_once('\n')
+        .ok_or_else(|| Error("unexpected end".into()))?;
+    *rest = after;
+    *number += 1;
+    Ok((*number, line.trim_end_matches('\r')))
+}
+
+fn bad(line: usize, expected: &str) -> Error {
+    Error(format!("line {}: expected {}", line, expected))
+}
+
+fn parse_node(words: &[&str]) -> Option<Node> {
+    let id = |s: &str| s.parse::<Id>().ok();
+    let k = |s: &str| match s {
+        "-" => Some(None),
+        _ => id(s).map(Some),
+    };
+    let c = |s: &str| s.parse().ok().and_then(char::from_u32);
+    let value = |v| Some(Node::Value(v));
+    let kont = |k| Some(Node::Kont(k));
+    match *words {
+        ["i"] => Some(Node::Atom(Term::I)),
+        ["s"] => Some(Node::Atom(Term::S)),
+        ["k"] => Some(Node::Atom(Term::K)),
+        ["v"] => Some(Node::Atom(Term::V)),
+        ["d"] => Some(Node::Atom(Term::D)),
+        ["c"] => Some(Node::Atom(Term::C)),
+        ["r"] => Some(Node::Atom(Term::R)),
+        ["put", x] => Some(Node::Atom(Term::Put(c(x)?))),
+        ["app", t0, t1] => Some(Node::App(id(t0)?, id(t1)?)),
+        ["at", start] => Some(Node::At(start.parse().ok()?)),
+        ["I0"] => value(Value::I0),
+        ["S0"] => value(Value::S0),
+        ["K0"] => value(Value::K0),
+        ["V0"] => value(Value::V0),
+        ["D0"] => value(Value::D0),
+        ["C0"] => value(Value::C0),
+        ["Put0", x] => value(Value::Put0(c(x)?)),
+        ["S1", x] => value(Value::S1(id(x)?)),
+        ["S2", x, y] => value(Value::S2(id(x)?, id(y)?)),
+        ["K1", x] => value(Value::K1(id(x)?)),
+        ["D1T", t] => value(Value::D1T(id(t)?)),
+        ["D1V", x] => value(Value::D1V(id(x)?)),
+        ["C1", k1] => value(Value::C1(k(k1)?)),
+        ["BindT", t, k1] => kont(Kont::BindT(id(t)?, k(k1)?)),
+        ["BindV", v, k1] => kont(Kont::BindV(id(v)?, k(k1)?)),
+        ["BindW", w, k1] => kont(Kont::BindW(id(w)?, k(k1)?)),
+        ["SWait", v1, v, k1] => kont(Kont::SWait(id(v1)?, id(v)?, k(k1)?)),
+        _ => None,
+    }
+}
+
+fn parse_config(words: &[&str]) -> Option<Config> {
+    let id = |s: &str| s.parse::<Id>().ok();
+    let k = |s: &str| match s {
+        "-" => Some(None),
+        _ => id(s).map(Some),
+    };
+    match *words {
+        ["Eval", t, k1] => Some(Config::Eval(id(t)?, k(k1)?)),
+        ["ApplyT", v, t, k1] => Some(Config::ApplyT(id(v)?, id(t)?, k(k1)?)),
+        ["ApplyV", v, w, k1] => Some(Config::ApplyV(id(v)?, id(w)?, k(k1)?)),
+        ["ApplyK", k1, v] => Some(Config::ApplyK(k(k1)?, id(v)?)),
+        _ => None,
+    }
+}
+
+// Every node of a program with a position, by where it starts. Each starts
+// at a different byte: an application at its backquote, anything else at
+// its first character.
+fn located(program: &Program) -> HashMap<usize, Arc<Term>> {
+    let mut located = HashMap::new();
+    let mut todo = vec![&program.term];
+    while let Some(t) = todo.pop() {
+        if let Some(span) = program.span(t) {
+            located.insert(span.start, t.clone());
+        }
+        if let Term::App(t0, t1) = t.as_ref() {
+            todo.push(t1);
+            todo.push(t0);
+        }
+    }
+    located
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machines::anaive;
+    use crate::machines::arc::{self, Local};
+
+    // a continuation taken and thrown, and a numeral applied 27 times
+    const SOURCES: [&str; 2] = [
+        "``cc`c`d`ki",
+        "`````s``s`ksk``s``s`kski``s``s`ksk``s``s`kski`kki",
+    ];
+
+    // Snapshots of the program on the arc machine after each number of steps
+    fn snapshots(program: &Program) -> Vec<Snapshot> {
+        let mut state = arc::new::<Local>(program.term.clone());
+        let mut snapshots = vec![state.snapshot(Some(program))];
+        while state.extract().is_none() {
+            state.step();
+            snapshots.push(state.snapshot(Some(program)));
+        }
+        snapshots
+    }
+
+    #[test]
+    fn text_reads_back_as_the_same_snapshot() {
+        for source in SOURCES {
+            let program = parse_program(source).unwrap();
+            for snapshot in snapshots(&program) {
+                let text = snapshot.text();
+                let read = Snapshot::read(&text).unwrap();
+                assert_eq!(format!("{:?}", read.nodes), format!("{:?}", snapshot.nodes));
+                assert_eq!(read.config, snapshot.config);
+                assert_eq!(read.steps, snapshot.steps);
+                assert_eq!(read.source, snapshot.source);
+                assert_eq!(read.text(), text);
+            }
+        }
+    }
+
+    #[test]
+    fn saved_on_arc_resumes_on_anaive() {
+        for source in SOURCES {
+            let program = parse_program(source).unwrap();
+            let straight = anaive::new(program.term.as_ref().clone()).run().to_string();
+            for snapshot in snapshots(&program) {
+                let snapshot = Snapshot::read(&snapshot.text()).unwrap();
+                let resumed = anaive::from_snapshot(&snapshot, Some(&program)).unwrap();
+                assert_eq!(resumed.run().to_string(), straight, "{}", source);
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_text_is_refused() {
+        let program = parse_program(SOURCES[0]).unwrap();
+        let snapshot = &snapshots(&program)[3];
+        let text = snapshot.text();
+        let next = snapshot.nodes.len();
+        let broken = [
+            String::new(),
+            text.replace(HEADER, "unabs snapshot 0"),
+            text.replace("steps ", "steps x"),
+            // cut short, before the state
+            text[..text.find("state").unwrap()].to_string(),
+            // a node holding one that comes after it
+            text.replace("state ", &format!("{} K1 {}\nstate ", next, next + 1)),
+            text.replace("state ", "state Nowhere "),
+            text.replace("source 11", "source 12"),
+        ];
+        for text in broken {
+            assert!(Snapshot::read(&text).is_err(), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use std::fmt::Display;
+
+// Counters describing how a machine ran
+
+/// Names of the transitions, as counted in `Stats::transitions`
+pub const TRANSITIONS: [&str; 4] = ["Eval", "ApplyT", "ApplyV", "ApplyK"];
+
+/// Names of the values being applied, as counted in `Stats::applications`
+pub const APPLICATIONS: [&str; 13] = [
+    "I0", "S0", "K0", "V0", "D0", "C0", "Put0", "S1", "S2", "K1", "D1T", "D1V", "C1",
+];
+
+/// The kinds of values being applied, in the order of `APPLICATIONS`. Both
+/// machines sort their values into these.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    I0,
+    S0,
+    K0,
+    V0,
+    D0,
+    C0,
+    Put0,
+    S1,
+    S2,
+    K1,
+    D1T,
+    D1V,
+    C1,
+}
+
+const _: () = assert!(ValueKind::C1 as usize + 1 == APPLICATIONS.len());
+
+impl ValueKind {
+    /// Position in `APPLICATIONS`, and in `Stats::applications`
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Names of the continuation frames, as counted in `Stats::frames`
+pub const FRAMES: [&str; 4] = ["BindT", "BindV", "BindW", "SWait"];
+
+pub const BIND_T: usize = 0;
+pub const BIND_V: usize = 1;
+pub const BIND_W: usize = 2;
+pub const S_WAIT: usize = 3;
+
+/// How to print stats
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    /// Transitions taken, by the kind of state they started from
+    pub transitions: [u64; 4],
+    /// Applications, by the kind of value applied
+    pub applications: [u64; 13],
+    /// Continuation frames built, by kind
+    pub frames: [u64; 4],
+    /// Most frames the continuation ever held
+    pub peak_depth: usize,
+    /// Characters printed
+    pub output: u64,
+}
+
+impl Stats {
+    /// Number of continuations captured by `c`
+    pub fn captures(&self) -> u64 {
+        self.applications[ValueKind::C0.index()]
+    }
+
+    /// Number of times a captured continuation was resumed
+    pub fn resumptions(&self) -> u64 {
+        self.applications[ValueKind::C1.index()]
+    }
+
+    pub fn format(&self, format: Format) -> String {
+        match format {
+            Format::Text => self.to_string(),
+            Format::Json => self.json(),
+        }
+    }
+
+    pub fn json(&self) -> String {
+        fn object(names: &[&str], counts: &[u64]) -> String {
+            let fields: Vec<String> = names
+                .iter()
+                .zip(counts)
+                .map(|(name, n)| format!("\"{}\":{}", name, n))
+                .collect();
+            format!("{{{}}}", fields.join(","))
+        }
+        format!(
+            "{{\"transitions\":{},\"applications\":{},\"frames\":{},\"peak_depth\":{},\"captures\":{},\"resumptions\":{},\"output\":{}}}",
+            object(&TRANSITIONS, &self.transitions),
+            object(&APPLICATIONS, &self.applications),
+            object(&FRAMES, &self.frames),
+            self.peak_depth,
+            self.captures(),
+            self.resumptions(),
+            self.output,
+        )
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn section(
+            f: &mut std::fmt::Formatter<'_>,
+            title: &str,
+            names: &[&str],
+            counts: &[u64],
+        ) -> std::fmt::Result {
+            writeln!(f, "{}: {}", title, counts.iter().sum::<u64>())?;
+            for (name, n) in names.iter().zip(counts) {
+                if *n > 0 {
+                    writeln!(f, "  {:<8}{:>12}", name, n)?;
+                }
+            }
+            Ok(())
+        }
+        section(f, "Transitions", &TRANSITIONS, &self.transitions)?;
+        section(f, "Applications", &APPLICATIONS, &self.applications)?;
+        section(f, "Frames", &FRAMES, &self.frames)?;
+        writeln!(f, "Peak depth: {}", self.peak_depth)?;
+        writeln!(f, "Captures: {}", self.captures())?;
+        writeln!(f, "Resumptions: {}", self.resumptions())?;
+        write!(f, "Output: {}", self.output)
+    }
+}
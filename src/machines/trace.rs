@@ -0,0 +1,146 @@
+use super::arc::{Kont, Share, State, StateFlag, Value};
+use super::render::Bounds;
+use super::stats::{APPLICATIONS, BIND_T, BIND_V, BIND_W, FRAMES, S_WAIT};
+use crate::term::{Program, Term};
+use std::fmt::Write as _;
+use std::io::Write;
+
+// Every transition of a run, one JSON object per line
+//
+// Each line is the transition taken from a state, described by that state:
+//
+//   {"step":12,"state":"ApplyV","rule":"Put0","term":null,"span":null,
+//    "at":null,"v":".H","w":"i","depth":3,"output":1,"printed":"H"}
+//
+// - `step`: transitions taken once this one is, from 1
+// - `state`: `Eval`, `ApplyT`, `ApplyV` or `ApplyK`
+// - `rule`: which rule of that state applies. For `Eval`, `App` or `Atom`;
+//   for `ApplyT`, `Delay` when the value is `d` and `Arg` otherwise; for
+//   `ApplyV`, the kind of value applied (`I0`, `S2`, `D1T`...); for `ApplyK`,
+//   the frame popped (`BindT`, `BindV`, `BindW` or `SWait`)
+// - `term`: the term being evaluated, or waiting in `ApplyT`, cut short
+// - `span`: its start and end in the source, in bytes, if it is part of it
+// - `at`: the line and column it starts at, as `L:C`
+// - `v`, `w`: the value and its argument, cut short, or null. Parts shown
+//   twice are labelled: `#1=` where first shown, `#1#` after that
+// - `depth`: frames in the continuation
+// - `output`: bytes printed once this transition is taken
+// - `printed`: the character this transition prints, or null
+//
+// Rules are not fused while tracing, so transitions line up one to one with
+// those of the machine in `docs/playground/am.js`.
+
+/// Longest term or value written, in characters.
+const SUMMARY: usize = 60;
+
+/// Writes the transitions of a run.
+pub struct Trace<W: Write> {
+    out: W,
+    // the first write that failed; nothing more is written after it
+    error: Option<std::io::Error>,
+}
+
+impl<W: Write> Trace<W> {
+    pub fn new(out: W) -> Self {
+        Trace { out, error: None }
+    }
+
+    /// Writes the transition about to be taken from a state, if the run
+    /// is not over.
+    pub fn record<P: Share>(&mut self, state: &State<P>, program: &Program) {
+        if self.error.is_some() || state.extract().is_some() {
+            return;
+        }
+        let line = line(state, program);
+        if let Err(e) = writeln!(self.out, "{}", line) {
+            self.error = Some(e);
+        }
+    }
+
+    /// Writes out what is buffered, giving the first error met.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.out.flush(),
+        }
+    }
+}
+
+fn line<P: Share>(state: &State<P>, program: &Program) -> String {
+    let flag = state.flag();
+    let v = state.v().map(|v| v.as_ref());
+    let rule = match (flag, state.term(), v) {
+        (StateFlag::Eval, Some(t), _) if matches!(t.as_ref(), Term::App(..)) => "App",
+        (StateFlag::Eval, _, _) => "Atom",
+        (StateFlag::ApplyT, _, Some(Value::D0)) => "Delay",
+        (StateFlag::ApplyT, _, _) => "Arg",
+        (StateFlag::ApplyV, _, Some(v)) => APPLICATIONS[v.kind().index()],
+        (StateFlag::ApplyK, _, _) => match state.k().map(|k| k.as_ref()) {
+            Some(Kont::BindT(..)) => FRAMES[BIND_T],
+            Some(Kont::BindV(..)) => FRAMES[BIND_V],
+            Some(Kont::BindW(..)) => FRAMES[BIND_W],
+            Some(Kont::SWait(..)) => FRAMES[S_WAIT],
+            None => unreachable!("the run is over"),
+        },
+        (StateFlag::ApplyV, _, None) => unreachable!("ApplyV holds a value"),
+    };
+    let printed = match (flag, v) {
+        (StateFlag::ApplyV, Some(Value::Put0(c))) => Some(*c),
+        _ => None,
+    };
+    let output = state.output() + printed.map_or(0, |c| c.len_utf8() as u64);
+    let span = state.term().and_then(|t| program.span(t));
+    let short = Bounds::SHORT.narrow(SUMMARY);
+
+    let mut out = String::new();
+    write!(
+        out,
+        "{{\"step\":{},\"state\":\"{:?}\",\"rule\":\"{}\",\"term\":{}",
+        state.steps() + 1,
+        flag,
+        rule,
+        or_null(state.term().map(|t| string(&short.render(t.as_ref())))),
+    )
+    .unwrap();
+    write!(
+        out,
+        ",\"span\":{},\"at\":{}",
+        or_null(span.map(|span| format!("[{},{}]", span.start, span.end))),
+        or_null(span.map(|span| format!("\"{}\"", span))),
+    )
+    .unwrap();
+    write!(
+        out,
+        ",\"v\":{},\"w\":{},\"depth\":{},\"output\":{},\"printed\":{}}}",
+        or_null(v.map(|v| string(&short.render(v)))),
+        or_null(state.w().map(|w| string(&short.render(w.as_ref())))),
+        state.depth(),
+        output,
+        or_null(printed.map(|c| string(&c.to_string()))),
+    )
+    .unwrap();
+    out
+}
+
+pub(crate) fn or_null(json: Option<String>) -> String {
+    json.unwrap_or_else(|| "null".to_string())
+}
+
+// s as a JSON string
+pub(crate) fn string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
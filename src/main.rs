@@ -1,10 +1,13 @@
-pub mod machines;
-pub mod term;
-
+use std::io::IsTerminal;
 use std::path::PathBuf;
+use std::time::Duration;
 
-use clap::Parser;
-use term::parse_term;
+use clap::{Parser, ValueEnum};
+use std::sync::Arc;
+use unabs::machines::render::Bounds;
+use unabs::machines::snapshot::Snapshot;
+use unabs::machines::{self, codec, output, repl, slice, stats, Limits, Options};
+use unabs::term::{parse_program, Program, Term};
 
 /// UnABS: Unlambda At Breakneck Speed
 #[derive(Parser, Debug)]
@@ -18,20 +21,341 @@ struct Cli {
     #[arg(short, long, group = "input")]
     file: Option<PathBuf>,
 
-    /// Enter interactive mode
+    /// Enter interactive mode: a debugger, or with -m anaive a stepper
     #[arg(short, long)]
     interactive: bool,
+
+    /// Start a read-eval-print loop instead of running a program
+    #[arg(long, conflicts_with_all = ["program", "file", "interactive", "slice"])]
+    repl: bool,
+
+    /// About how much memory, in MiB, the states the debugger keeps to step back to may take; the further back, the fewer are kept
+    #[arg(long, value_name = "MIB", default_value_t = 256)]
+    history_memory: usize,
+
+    /// Show whole terms, values and continuations in the stepper and debugger, rather than a line or two of each
+    #[arg(long)]
+    full: bool,
+
+    /// Print the result as a term that evaluates to it, so it can be run or put in another program. Results holding a continuation have none
+    #[arg(long)]
+    readback: bool,
+
+    /// Where to print the final value, apart from the program's output. By default stdout if it is a terminal or --readback or --result-as is given, and nowhere otherwise
+    #[arg(long, value_enum, value_name = "MODE")]
+    result: Option<ResultMode>,
+
+    /// When to write out what the program prints: after each character, at the end of each line, or in large blocks. By default line on a terminal and full otherwise; the stepper and debugger always write each character
+    #[arg(long, value_enum, value_name = "POLICY")]
+    flush: Option<FlushPolicy>,
+
+    /// Print the result as the data it encodes: a Church numeral, a Church boolean, or a Scott list of numerals
+    #[arg(long, value_enum, value_name = "KIND", conflicts_with = "readback")]
+    result_as: Option<ResultAs>,
+
+    /// Abstract machine to run the program on. By default anaive, or arc with -i
+    #[arg(short, long, value_enum)]
+    machine: Option<Machine>,
+
+    /// Apply effect-free combinator shapes, and promises of terms that only
+    /// build such values, in fewer steps (arc only)
+    #[arg(long)]
+    simplify: bool,
+
+    /// Take at most this many steps (arc only)
+    #[arg(long, value_name = "N")]
+    max_steps: Option<u64>,
+
+    /// Stop when more than this many values are alive (arc only)
+    #[arg(long, value_name = "N")]
+    max_values: Option<usize>,
+
+    /// Stop when the continuation is more than this many frames deep (arc only)
+    #[arg(long, value_name = "N")]
+    max_depth: Option<usize>,
+
+    /// Print at most this many bytes, stopping before the character that would go over (arc only)
+    #[arg(long, value_name = "BYTES")]
+    max_output: Option<u64>,
+
+    /// Stop after this many seconds (arc only)
+    #[arg(long, value_name = "SECS", value_parser = seconds)]
+    timeout: Option<Duration>,
+
+    /// Print execution stats to stderr at the end of the run
+    #[arg(long, value_enum, value_name = "FORMAT", num_args = 0..=1, require_equals = true, default_missing_value = "text")]
+    stats: Option<StatsFormat>,
+
+    /// Profile which subterms the steps go to, writing the report to FILE or to stderr (arc only)
+    #[arg(long, value_name = "FILE", num_args = 0..=1, require_equals = true)]
+    profile: Option<Option<PathBuf>>,
+
+    /// Sample the continuation and write the stacks to FILE, in folded format for flamegraph tools (arc only)
+    #[arg(long, value_name = "FILE")]
+    flamegraph: Option<PathBuf>,
+
+    /// Steps between two flamegraph samples
+    #[arg(long, value_name = "N", default_value_t = 1000)]
+    sample_every: u64,
+
+    /// Write which terms ran to FILE, as HTML if it ends in .html and as text otherwise (arc only)
+    #[arg(long, value_name = "FILE")]
+    coverage: Option<PathBuf>,
+
+    /// Write the step, source position and depth of each printed character to FILE (arc only)
+    #[arg(long, value_name = "FILE")]
+    provenance: Option<PathBuf>,
+
+    /// Show which terms the final value (`result`) or the character printed at position N depends on. Runs on a separate, slower machine
+    #[arg(long, value_name = "result|N")]
+    slice: Option<slice::Target>,
+
+    /// Write each transition to FILE as a line of JSON, one rule at a time (arc only)
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["interactive", "simplify"])]
+    trace: Option<PathBuf>,
+
+    /// Write the values and frames the machine holds when the run ends to FILE, as a Graphviz graph showing what is shared (arc only)
+    #[arg(long, value_name = "FILE")]
+    dot: Option<PathBuf>,
+
+    /// Write the machine state to FILE if the run stops early: at a limit, or on Ctrl-C and save
+    #[arg(long, value_name = "FILE")]
+    snapshot: Option<PathBuf>,
+
+    /// Take up a run saved with --snapshot, on either machine
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["program", "file", "repl", "slice"])]
+    resume: Option<PathBuf>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Machine {
+    /// The copying machine
+    Anaive,
+    /// The sharing machine
+    Arc,
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Cli::parse();
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ResultMode {
+    /// Do not print it
+    None,
+    /// After the program's output
+    Stdout,
+    Stderr,
+    /// To stderr, as a line of JSON
+    Json,
+}
 
-    let program = args.program.unwrap_or_else(|| {
-        std::fs::read_to_string(args.file.unwrap()).expect("Could not read file")
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum FlushPolicy {
+    None,
+    Line,
+    Full,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ResultAs {
+    Nat,
+    Bool,
+    List,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum StatsFormat {
+    Text,
+    Json,
+}
+
+impl From<Machine> for repl::Machine {
+    fn from(machine: Machine) -> Self {
+        match machine {
+            Machine::Anaive => repl::Machine::Anaive,
+            Machine::Arc => repl::Machine::Arc,
+        }
+    }
+}
+
+impl From<ResultMode> for machines::ResultMode {
+    fn from(mode: ResultMode) -> Self {
+        match mode {
+            ResultMode::None => machines::ResultMode::None,
+            ResultMode::Stdout => machines::ResultMode::Stdout,
+            ResultMode::Stderr => machines::ResultMode::Stderr,
+            ResultMode::Json => machines::ResultMode::Json,
+        }
+    }
+}
+
+impl From<FlushPolicy> for output::Flush {
+    fn from(policy: FlushPolicy) -> Self {
+        match policy {
+            FlushPolicy::None => output::Flush::None,
+            FlushPolicy::Line => output::Flush::Line,
+            FlushPolicy::Full => output::Flush::Full,
+        }
+    }
+}
+
+impl From<ResultAs> for codec::Kind {
+    fn from(kind: ResultAs) -> Self {
+        match kind {
+            ResultAs::Nat => codec::Kind::Nat,
+            ResultAs::Bool => codec::Kind::Bool,
+            ResultAs::List => codec::Kind::List,
+        }
+    }
+}
+
+impl From<StatsFormat> for stats::Format {
+    fn from(format: StatsFormat) -> Self {
+        match format {
+            StatsFormat::Text => stats::Format::Text,
+            StatsFormat::Json => stats::Format::Json,
+        }
+    }
+}
+
+// A length of time in seconds, which may have a fraction
+fn seconds(s: &str) -> Result<Duration, String> {
+    let secs: f64 = s
+        .parse()
+        .map_err(|_| format!("expected seconds, got `{}`", s))?;
+    Duration::try_from_secs_f64(secs).map_err(|_| format!("expected seconds, got `{}`", s))
+}
+
+fn main() {
+    let result = run(Cli::parse());
+    // whatever way the run ended, what the program printed is written out
+    output::flush();
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(args: Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let limits = Limits {
+        steps: args.max_steps,
+        values: args.max_values,
+        depth: args.max_depth,
+        output: args.max_output,
+        time: args.timeout,
+    };
+    let read = args.readback || args.result_as.is_some();
+    let options = Options {
+        interactive: args.interactive,
+        history: args.history_memory << 20,
+        bounds: if args.full {
+            Bounds::FULL
+        } else {
+            Bounds::SHORT
+        },
+        simplify: args.simplify,
+        limits,
+        stats: args.stats.map(Into::into),
+        profile: args.profile,
+        flamegraph: args.flamegraph,
+        sample_every: args.sample_every,
+        coverage: args.coverage,
+        provenance: args.provenance,
+        slice: args.slice,
+        snapshot: args.snapshot,
+        trace: args.trace,
+        dot: args.dot,
+        readback: args.readback,
+        result_as: args.result_as.map(Into::into),
+        // only the program's output goes down a pipe, unless the result
+        // was asked for in a form to be read
+        result: args.result.map_or_else(
+            || {
+                if std::io::stdout().is_terminal() || read {
+                    machines::ResultMode::Stdout
+                } else {
+                    machines::ResultMode::None
+                }
+            },
+            Into::into,
+        ),
+    };
+    // the debugger needs the arc machine, and has it unless told otherwise
+    let machine = args.machine.unwrap_or(if args.interactive {
+        Machine::Arc
+    } else {
+        Machine::Anaive
+    });
+    // printed characters show up between steps
+    output::set(match (options.interactive, args.flush) {
+        (true, _) => output::Flush::None,
+        (false, Some(policy)) => policy.into(),
+        (false, None) if std::io::stdout().is_terminal() => output::Flush::Line,
+        (false, None) => output::Flush::Full,
     });
 
-    let term = parse_term(program.trim())?;
-    // println!("Term:\n{}\n", term);
-    machines::anaive::main(term, args.interactive);
+    if args.repl {
+        if matches!(machine, Machine::Anaive) && !options.limits.is_unlimited() {
+            return Err("resource limits need the arc machine (-m arc)".into());
+        }
+        if matches!(machine, Machine::Anaive) && options.simplify {
+            return Err("simplifying needs the arc machine (-m arc)".into());
+        }
+        return repl::main(machine.into(), &options);
+    }
+    let snapshot = args.resume.as_deref().map(Snapshot::load).transpose()?;
+    let program = match &snapshot {
+        // a snapshot of a term with no source runs as a program without one
+        Some(snapshot) => snapshot
+            .program()?
+            .unwrap_or_else(|| Program::from_term(Arc::new(Term::I))),
+        None => {
+            let program = args.program.unwrap_or_else(|| {
+                std::fs::read_to_string(args.file.unwrap()).expect("Could not read file")
+            });
+            parse_program(&program)?
+        }
+    };
+    // println!("Term:\n{}\n", program.term);
+    if let Some(target) = options.slice {
+        if options.interactive || !options.limits.is_unlimited() {
+            return Err("slicing cannot be combined with -i or resource limits".into());
+        }
+        slice::main(&program, target, &options);
+        return Ok(());
+    }
+    match machine {
+        Machine::Anaive if !options.limits.is_unlimited() => {
+            return Err("resource limits need the arc machine (-m arc)".into());
+        }
+        Machine::Anaive if options.simplify => {
+            return Err("simplifying needs the arc machine (-m arc)".into());
+        }
+        Machine::Anaive if options.profile.is_some() || options.flamegraph.is_some() => {
+            return Err("profiling needs the arc machine (-m arc)".into());
+        }
+        Machine::Anaive if options.coverage.is_some() => {
+            return Err("coverage needs the arc machine (-m arc)".into());
+        }
+        Machine::Anaive if options.provenance.is_some() => {
+            return Err("output provenance needs the arc machine (-m arc)".into());
+        }
+        Machine::Anaive if options.trace.is_some() => {
+            return Err("tracing needs the arc machine (-m arc)".into());
+        }
+        Machine::Anaive if options.dot.is_some() => {
+            return Err("graphs need the arc machine (-m arc)".into());
+        }
+        Machine::Anaive => {
+            let state = match &snapshot {
+                Some(snapshot) => machines::anaive::from_snapshot(snapshot, Some(&program))?,
+                None => machines::anaive::new(program.term.as_ref().clone()),
+            };
+            machines::anaive::main(state, &options)
+        }
+        Machine::Arc => {
+            if machines::arc::main(&program, snapshot.as_ref(), &options).is_err() {
+                output::flush();
+                std::process::exit(2);
+            }
+        }
+    }
     Ok(())
 }
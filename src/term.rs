@@ -1,6 +1,8 @@
 use pest::Parser;
 use pest_derive::Parser;
+use std::collections::HashMap;
 use std::fmt::Display;
+use std::sync::Arc;
 
 #[derive(Debug, Clone)]
 pub enum Term {
@@ -12,7 +14,7 @@ pub enum Term {
     C,
     R,
     Put(char),
-    App(Box<Term>, Box<Term>),
+    App(Arc<Term>, Arc<Term>),
     // Can do better: a flat AST
 }
 
@@ -20,6 +22,107 @@ pub enum Term {
 #[grammar = "unlambda.pest"]
 struct UnParser;
 
+/// Where a node comes from in the source: its bytes, and the line and
+/// column (counted in characters, both from 1) where it starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+/// A parsed program, along with the span of each of its nodes.
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub source: String,
+    pub term: Arc<Term>,
+    // by node address; holding the root keeps every address in use
+    spans: HashMap<usize, Span>,
+}
+
+impl Program {
+    /// A program with no source, so none of its nodes have spans.
+    pub fn from_term(term: Arc<Term>) -> Self {
+        Program {
+            source: String::new(),
+            term,
+            spans: HashMap::new(),
+        }
+    }
+
+    /// The span of a node of this program. Nodes are told apart by address,
+    /// so this only knows the nodes of `self.term`, not copies of them.
+    pub fn span(&self, t: &Term) -> Option<Span> {
+        self.spans.get(&(t as *const Term as usize)).copied()
+    }
+
+    /// The source text of a node of this program.
+    pub fn text(&self, t: &Term) -> Option<&str> {
+        self.span(t).map(|span| &self.source[span.start..span.end])
+    }
+}
+
+// Turns byte offsets into lines and columns. Offsets must come in order,
+// which they do when the tree is walked in preorder.
+struct Locator<'a> {
+    source: &'a str,
+    pos: usize,
+    line: usize,
+    col: usize,
+}
+
+impl Locator<'_> {
+    fn span(&mut self, start: usize, end: usize) -> Span {
+        for c in self.source[self.pos..start].chars() {
+            if c == '\n' {
+                self.line += 1;
+                self.col = 1;
+            } else {
+                self.col += 1;
+            }
+        }
+        self.pos = start;
+        Span {
+            start,
+            end,
+            line: self.line,
+            col: self.col,
+        }
+    }
+}
+
+fn parse_to_located_term(
+    pair: pest::iterators::Pair<Rule>,
+    offset: usize,
+    locator: &mut Locator,
+    spans: &mut HashMap<usize, Span>,
+) -> Arc<Term> {
+    if pair.as_rule() == Rule::term {
+        let inner = pair.into_inner().next().unwrap();
+        return parse_to_located_term(inner, offset, locator, spans);
+    }
+    let pest_span = pair.as_span();
+    let span = locator.span(offset + pest_span.start(), offset + pest_span.end());
+    let t = match pair.as_rule() {
+        Rule::app => {
+            let mut pairs = pair.into_inner();
+            let t0 = parse_to_located_term(pairs.next().unwrap(), offset, locator, spans);
+            let t1 = parse_to_located_term(pairs.next().unwrap(), offset, locator, spans);
+            Arc::new(Term::App(t0, t1))
+        }
+        _ => Arc::new(parse_to_term(pair)),
+    };
+    spans.insert(Arc::as_ptr(&t) as usize, span);
+    t
+}
+
 fn parse_to_term(pair: pest::iterators::Pair<Rule>) -> Term {
     match pair.as_rule() {
         Rule::term => parse_to_term(pair.into_inner().next().unwrap()),
@@ -42,12 +145,13 @@ fn parse_to_term(pair: pest::iterators::Pair<Rule>) -> Term {
             let mut pairs = pair.into_inner();
             let t0 = parse_to_term(pairs.next().unwrap());
             let t1 = parse_to_term(pairs.next().unwrap());
-            Term::App(Box::new(t0), Box::new(t1))
+            Term::App(Arc::new(t0), Arc::new(t1))
         }
         _ => unreachable!(),
     }
 }
 
+#[allow(clippy::result_large_err)]
 pub fn parse_term(s: &str) -> Result<Term, pest::error::Error<Rule>> {
     let parsed = UnParser::parse(Rule::main, s)?
         .next()
@@ -58,6 +162,80 @@ pub fn parse_term(s: &str) -> Result<Term, pest::error::Error<Rule>> {
     Ok(parse_to_term(term))
 }
 
+/// Parses a whole source file, keeping track of where each node came from.
+pub fn parse_program(source: &str) -> Result<Program, Box<pest::error::Error<Rule>>> {
+    let trimmed = source.trim();
+    let offset = source.len() - source.trim_start().len();
+    let parsed = UnParser::parse(Rule::main, trimmed)?.next().unwrap();
+    let term = parsed.into_inner().next().unwrap();
+    let mut locator = Locator {
+        source,
+        pos: 0,
+        line: 1,
+        col: 1,
+    };
+    let mut spans = HashMap::new();
+    let term = parse_to_located_term(term, offset, &mut locator, &mut spans);
+    Ok(Program {
+        source: source.to_string(),
+        term,
+        spans,
+    })
+}
+
+/// Something typed at the REPL.
+#[derive(Debug, Clone)]
+pub enum Entry {
+    /// A name for a term
+    Define(String, Arc<Term>),
+    /// A term to evaluate
+    Eval(Arc<Term>),
+}
+
+/// Parses a line typed at the REPL, putting in each `$name` the term it
+/// was defined as. Definitions are shared, not copied.
+pub fn parse_entry(
+    s: &str,
+    defs: &HashMap<String, Arc<Term>>,
+) -> Result<Entry, Box<pest::error::Error<Rule>>> {
+    let parsed = UnParser::parse(Rule::entry, s)?.next().unwrap();
+    let entry = parsed.into_inner().next().unwrap();
+    match entry.as_rule() {
+        Rule::definition => {
+            let mut pairs = entry.into_inner();
+            let name = pairs.next().unwrap().as_str().to_string();
+            Ok(Entry::Define(name, parse_to_expr(pairs.next().unwrap(), defs)?))
+        }
+        _ => Ok(Entry::Eval(parse_to_expr(entry, defs)?)),
+    }
+}
+
+fn parse_to_expr(
+    pair: pest::iterators::Pair<Rule>,
+    defs: &HashMap<String, Arc<Term>>,
+) -> Result<Arc<Term>, Box<pest::error::Error<Rule>>> {
+    match pair.as_rule() {
+        Rule::expr => parse_to_expr(pair.into_inner().next().unwrap(), defs),
+        Rule::expr_app => {
+            let mut pairs = pair.into_inner();
+            let t0 = parse_to_expr(pairs.next().unwrap(), defs)?;
+            let t1 = parse_to_expr(pairs.next().unwrap(), defs)?;
+            Ok(Arc::new(Term::App(t0, t1)))
+        }
+        Rule::name => {
+            let name = &pair.as_str()[1..];
+            defs.get(name).cloned().ok_or_else(|| {
+                let message = format!("`{}` is not defined", name);
+                Box::new(pest::error::Error::new_from_span(
+                    pest::error::ErrorVariant::CustomError { message },
+                    pair.as_span(),
+                ))
+            })
+        }
+        _ => Ok(Arc::new(parse_to_term(pair))),
+    }
+}
+
 impl Display for Term {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {